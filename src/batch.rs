@@ -0,0 +1,231 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{
+    FutureExt, Stream, StreamExt,
+    future::BoxFuture,
+    stream::{AbortHandle, Abortable, FuturesUnordered},
+};
+
+use crate::{ClientBuilder, Error, Request, Response};
+
+const DEFAULT_LIMIT: usize = 64;
+const DEFAULT_PER_HOST: usize = 8;
+
+type Fetched = (usize, String, Result<Response, Error>);
+
+/// Runs many requests with a global and a per-host cap on concurrent
+/// requests. Results are yielded as they complete, or in request order.
+#[derive(Clone, Debug)]
+pub struct Batch {
+    builder: ClientBuilder,
+    limit: usize,
+    per_host: usize,
+    ordered: bool,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch {
+            builder: ClientBuilder::new(),
+            limit: DEFAULT_LIMIT,
+            per_host: DEFAULT_PER_HOST,
+            ordered: false,
+        }
+    }
+
+    /// Settings of `builder`, like proxies, limiters and redirects, are used
+    /// for every request. Its URL, method, headers and body are ignored.
+    pub fn client(mut self, builder: ClientBuilder) -> Batch {
+        self.builder = builder;
+        self
+    }
+
+    /// Requests running at the same time, 64 by default.
+    pub fn limit(mut self, limit: usize) -> Batch {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /// Requests running at the same time against one host, 8 by default.
+    pub fn per_host(mut self, per_host: usize) -> Batch {
+        self.per_host = per_host.max(1);
+        self
+    }
+
+    /// Yields results in the order of the requests instead of as they
+    /// complete. Results that complete early are held back until then.
+    pub fn ordered(mut self, ordered: bool) -> Batch {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Starts the requests as the returned stream is polled. Items carry the
+    /// position of their request in `requests`.
+    pub fn run<I>(&self, requests: I) -> Fetches
+    where
+        I: IntoIterator<Item = Request>,
+        I::IntoIter: Send + 'static,
+    {
+        let scheduler = Scheduler {
+            requests: Box::new(requests.into_iter()),
+            next_index: 0,
+            queued: VecDeque::new(),
+            running: FuturesUnordered::new(),
+            hosts: HashMap::new(),
+            done: BTreeMap::new(),
+            yielded: 0,
+            batch: self.clone(),
+        };
+        let (inner, handle) = futures::stream::abortable(scheduler);
+        Fetches { inner, handle }
+    }
+}
+
+/// Results of a [`Batch`], as a [`Stream`] of request positions and results.
+pub struct Fetches {
+    inner: Abortable<Scheduler>,
+    handle: AbortHandle,
+}
+
+impl Fetches {
+    /// Handle that cancels the whole batch: requests in flight are dropped,
+    /// no new ones are started and the stream ends.
+    pub fn cancel_handle(&self) -> AbortHandle {
+        self.handle.clone()
+    }
+
+    pub fn cancel(&self) {
+        self.handle.abort();
+    }
+}
+
+impl fmt::Debug for Fetches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Fetches")
+            .field("cancelled", &self.handle.is_aborted())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Stream for Fetches {
+    type Item = (usize, Result<Response, Error>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().inner.poll_next_unpin(cx)
+    }
+}
+
+struct Scheduler {
+    requests: Box<dyn Iterator<Item = Request> + Send>,
+    next_index: usize,
+    /// Requests waiting for a free slot of their host.
+    queued: VecDeque<(usize, Request)>,
+    running: FuturesUnordered<BoxFuture<'static, Fetched>>,
+    /// Running requests per host.
+    hosts: HashMap<String, usize>,
+    /// Completed results waiting for their turn in ordered mode.
+    done: BTreeMap<usize, Result<Response, Error>>,
+    yielded: usize,
+    batch: Batch,
+}
+
+impl Scheduler {
+    /// Starts requests until the global cap is reached. Requests to hosts at
+    /// their cap are set aside, at most `limit` of them, so requests to other
+    /// hosts can pass them.
+    fn fill(&mut self) {
+        while self.running.len() < self.batch.limit {
+            let free = |hosts: &HashMap<String, usize>, request: &Request| {
+                hosts
+                    .get(&host(request))
+                    .is_none_or(|n| *n < self.batch.per_host)
+            };
+            if let Some(pos) = self
+                .queued
+                .iter()
+                .position(|(_, request)| free(&self.hosts, request))
+                && let Some((index, request)) = self.queued.remove(pos)
+            {
+                self.start(index, request);
+                continue;
+            }
+            if self.queued.len() >= self.batch.limit {
+                break;
+            }
+            let Some(request) = self.requests.next() else {
+                break;
+            };
+            let index = self.next_index;
+            self.next_index += 1;
+            if free(&self.hosts, &request) {
+                self.start(index, request);
+            } else {
+                self.queued.push_back((index, request));
+            }
+        }
+    }
+
+    fn start(&mut self, index: usize, request: Request) {
+        let host = host(&request);
+        *self.hosts.entry(host.clone()).or_default() += 1;
+        let builder = ClientBuilder::from_request(&self.batch.builder, request);
+        self.running.push(
+            async move {
+                let result = match builder.build().await {
+                    Ok(mut client) => client.send().await,
+                    Err(err) => Err(err),
+                };
+                (index, host, result)
+            }
+            .boxed(),
+        );
+    }
+}
+
+impl Stream for Scheduler {
+    type Item = (usize, Result<Response, Error>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(result) = this.done.remove(&this.yielded) {
+                this.yielded += 1;
+                return Poll::Ready(Some((this.yielded - 1, result)));
+            }
+            this.fill();
+            let Some((index, host, result)) = futures::ready!(this.running.poll_next_unpin(cx))
+            else {
+                return Poll::Ready(None);
+            };
+            if let Some(running) = this.hosts.get_mut(&host) {
+                *running -= 1;
+                if *running == 0 {
+                    this.hosts.remove(&host);
+                }
+            }
+            if !this.batch.ordered {
+                return Poll::Ready(Some((index, result)));
+            }
+            this.done.insert(index, result);
+        }
+    }
+}
+
+fn host(request: &Request) -> String {
+    request
+        .url
+        .host_str()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
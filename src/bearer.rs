@@ -0,0 +1,54 @@
+use std::{fmt, sync::Arc};
+
+use futures::future::BoxFuture;
+
+use crate::{Challenge, Error};
+
+/// Source of OAuth bearer tokens.
+///
+/// `token` is asked before each request. When a server rejects the token with
+/// `WWW-Authenticate: Bearer error="invalid_token"`, `refresh` is called once
+/// and the request is replayed with the token returned by the next `token`.
+pub trait TokenProvider: Send + Sync {
+    fn token(&self) -> BoxFuture<'_, Result<String, Error>>;
+
+    fn refresh(&self) -> BoxFuture<'_, Result<(), Error>>;
+}
+
+#[derive(Clone)]
+pub(crate) struct TokenSource(pub(crate) Arc<dyn TokenProvider>);
+
+impl fmt::Debug for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("TokenSource").finish()
+    }
+}
+
+impl PartialEq for TokenSource {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TokenSource {}
+
+/// Whether a challenge rejects the bearer token as invalid.
+pub(crate) fn is_invalid_token(challenge: &Challenge) -> bool {
+    challenge.has_scheme("Bearer") && challenge.param("error") == Some("invalid_token")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_token_challenge() {
+        let challenges = Challenge::parse_list(
+            r#"Basic realm="example", Bearer realm="example", error="invalid_token""#,
+        );
+        assert!(!is_invalid_token(&challenges[0]));
+        assert!(is_invalid_token(&challenges[1]));
+        let challenges = Challenge::parse_list(r#"Bearer realm="example""#);
+        assert!(!is_invalid_token(&challenges[0]));
+    }
+}
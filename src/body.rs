@@ -0,0 +1,180 @@
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{BufStream, Error, HttpStream, Response};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Bytes left of a `Content-Length` body.
+    Length(usize),
+    /// Bytes left of the current chunk, `0` before a chunk size line.
+    Chunked(usize),
+    /// Body delimited by the end of the connection.
+    Eof,
+    Done,
+}
+
+/// Response body read incrementally from the connection. Every piece read is
+/// reported to the download progress callback of the client.
+#[derive(Debug)]
+pub struct Body<S = HttpStream> {
+    stream: BufStream<S>,
+    state: State,
+    /// Bytes of the body read so far.
+    transferred: usize,
+    total: Option<usize>,
+}
+
+impl<S> Body<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Body of `response`, whose head was just read from `stream`.
+    pub fn new(stream: BufStream<S>, response: &Response) -> Body<S> {
+        let state = match (
+            response.has_body(),
+            response.has_chuncked_body(),
+            response.content_len(),
+        ) {
+            (false, _, _) | (true, false, Some(0)) => State::Done,
+            (true, false, Some(size)) => State::Length(size),
+            (true, true, _) => State::Chunked(0),
+            (true, false, None) => State::Eof,
+        };
+        Body {
+            stream,
+            state,
+            transferred: 0,
+            total: match state {
+                State::Length(size) => Some(size),
+                State::Done => Some(0),
+                _ => None,
+            },
+        }
+    }
+
+    /// Next piece of the body as it arrives, `None` after the end of the body.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        let chunk = self.next_chunk().await?;
+        if let Some(data) = &chunk {
+            self.transferred += data.len();
+            self.stream.report_download(self.transferred, self.total);
+        }
+        Ok(chunk)
+    }
+
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        match self.state {
+            State::Done => Ok(None),
+            State::Length(remaining) => {
+                let data = self.read(remaining).await?;
+                self.state = match remaining - data.len() {
+                    0 => State::Done,
+                    remaining => State::Length(remaining),
+                };
+                Ok(Some(data))
+            }
+            State::Chunked(mut remaining) => {
+                if remaining == 0 {
+                    remaining = self.stream.read_chunk_line().await?;
+                    if remaining == 0 {
+                        self.stream.skip_trailers().await?;
+                        self.state = State::Done;
+                        return Ok(None);
+                    }
+                }
+                let data = self.read(remaining).await?;
+                remaining -= data.len();
+                if remaining == 0 {
+                    self.stream.read_chunk_end().await?;
+                }
+                self.state = State::Chunked(remaining);
+                Ok(Some(data))
+            }
+            State::Eof => {
+                let data = self.stream.read_some(usize::MAX).await?;
+                if data.is_empty() {
+                    self.state = State::Done;
+                    return Ok(None);
+                }
+                Ok(Some(data))
+            }
+        }
+    }
+
+    /// Reads the rest of the body.
+    pub async fn bytes(mut self) -> Result<Bytes, Error> {
+        let mut body = BytesMut::new();
+        while let Some(chunk) = self.chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body.freeze())
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.state == State::Done
+    }
+
+    pub fn into_inner(self) -> BufStream<S> {
+        self.stream
+    }
+
+    async fn read(&mut self, max: usize) -> Result<Bytes, Error> {
+        let data = self.stream.read_some(max).await?;
+        if data.is_empty() {
+            return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncWriteExt, duplex};
+
+    use super::*;
+
+    async fn body(response: &'static [u8]) -> Body<tokio::io::DuplexStream> {
+        let (mut server, client) = duplex(8);
+        tokio::spawn(async move { server.write_all(response).await.unwrap() });
+        let mut stream = BufStream::new(client);
+        let response = stream.get_head().await.unwrap();
+        Body::new(stream, &response)
+    }
+
+    #[tokio::test]
+    async fn content_length_pieces() {
+        let mut body = body(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello worldEXTRA").await;
+        let mut pieces = Vec::new();
+        while let Some(chunk) = body.chunk().await.unwrap() {
+            pieces.push(chunk);
+        }
+        assert!(pieces.len() > 1);
+        assert_eq!(pieces.concat(), b"hello world");
+        assert_eq!(body.into_inner().buffer(), b"EXTRA");
+    }
+
+    #[tokio::test]
+    async fn chunked() {
+        let body = body(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        )
+        .await;
+        assert_eq!(&body.bytes().await.unwrap()[..], b"hello world");
+    }
+
+    #[tokio::test]
+    async fn until_eof() {
+        let mut body = body(b"HTTP/1.0 200 OK\r\n\r\nhello").await;
+        assert_eq!(body.chunk().await.unwrap().unwrap(), "hello");
+        assert_eq!(body.chunk().await.unwrap(), None);
+        assert!(body.is_end());
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let body = body(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello").await;
+        assert!(matches!(body.bytes().await, Err(Error::Io(_))));
+    }
+}
@@ -0,0 +1,531 @@
+use std::{
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{Error, HttpStream, Progress, Response, progress::ProgressFn};
+
+const BUFFER_CAPACITY: usize = 8192;
+const UPLOAD_PIECE_SIZE: usize = 16384;
+const CHUNK_MAX_LINE_LENGTH: usize = 4096;
+const HEADERS_MAX_LENGTH: usize = 4096;
+/// Most memory reserved up front for a body length announced by the server.
+const MAX_RESERVE: usize = 1 << 20;
+
+/// Buffered reader around a stream that parses responses directly from its
+/// internal buffer.
+///
+/// Bytes are read from the inner stream in large chunks. The status line and
+/// headers are parsed in place and body bytes that arrived together with the
+/// head are split off the buffer without copying. Bytes received after the
+/// response stay in the buffer and are returned first by `AsyncRead`.
+pub struct BufStream<S = HttpStream> {
+    inner: S,
+    buf: BytesMut,
+    upload: Option<ProgressFn>,
+    download: Option<ProgressFn>,
+    /// Start of the current exchange, for the elapsed time of progress reports.
+    start: Instant,
+}
+
+impl<S> BufStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(inner: S) -> Self {
+        BufStream::with_capacity(BUFFER_CAPACITY, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: S) -> Self {
+        BufStream {
+            inner,
+            buf: BytesMut::with_capacity(capacity),
+            upload: None,
+            download: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Sets the callbacks reporting request and response body bytes, and
+    /// starts timing a new exchange.
+    pub(crate) fn set_progress(
+        &mut self,
+        upload: Option<ProgressFn>,
+        download: Option<ProgressFn>,
+    ) {
+        self.upload = upload;
+        self.download = download;
+        self.start = Instant::now();
+    }
+
+    /// Reports `transferred` response body bytes to the download callback.
+    pub(crate) fn report_download(&self, transferred: usize, total: Option<usize>) {
+        if let Some(callback) = &self.download {
+            self.report(callback, transferred, total);
+        }
+    }
+
+    fn report(&self, callback: &ProgressFn, transferred: usize, total: Option<usize>) {
+        callback.call(Progress {
+            transferred: transferred as u64,
+            total: total.map(|total| total as u64),
+            elapsed: self.start.elapsed(),
+        });
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Bytes already read from the inner stream but not consumed yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Returns the inner stream together with any buffered bytes not consumed yet.
+    pub fn into_parts(self) -> (S, Bytes) {
+        (self.inner, self.buf.freeze())
+    }
+
+    async fn fill_buf(&mut self) -> Result<usize, Error> {
+        if self.buf.capacity() - self.buf.len() < BUFFER_CAPACITY / 2 {
+            self.buf.reserve(BUFFER_CAPACITY);
+        }
+        let n = self.inner.read_buf(&mut self.buf).await?;
+        Ok(n)
+    }
+
+    async fn fill_to(&mut self, len: usize) -> Result<(), Error> {
+        if self.buf.len() < len {
+            self.buf.reserve((len - self.buf.len()).min(MAX_RESERVE));
+        }
+        while self.buf.len() < len {
+            if self.fill_buf().await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads up to `max` bytes, waiting on the inner stream only when nothing
+    /// is buffered. Returns empty bytes at the end of the stream.
+    pub async fn read_some(&mut self, max: usize) -> Result<Bytes, Error> {
+        if self.buf.is_empty() && self.fill_buf().await? == 0 {
+            return Ok(Bytes::new());
+        }
+        let len = self.buf.len().min(max);
+        Ok(self.buf.split_to(len).freeze())
+    }
+
+    pub async fn send_msg(&mut self, msg: &[u8]) -> Result<(), Error> {
+        self.inner.write_all(msg).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    /// Sends a request whose body makes up the last `body_len` bytes of
+    /// `msg`. With an upload callback the body is written in pieces and
+    /// reported after each one.
+    pub(crate) async fn send_request(&mut self, msg: &[u8], body_len: usize) -> Result<(), Error> {
+        let Some(callback) = self.upload.clone().filter(|_| body_len > 0) else {
+            return self.send_msg(msg).await;
+        };
+        let (head, body) = msg.split_at(msg.len() - body_len);
+        self.inner.write_all(head).await?;
+        let mut sent = 0;
+        for piece in body.chunks(UPLOAD_PIECE_SIZE) {
+            self.inner.write_all(piece).await?;
+            sent += piece.len();
+            self.report(&callback, sent, Some(body_len));
+        }
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    pub async fn get_head(&mut self) -> Result<Response, Error> {
+        let mut searched = 0;
+        let head_len = loop {
+            if let Some(pos) = find_head_end(&self.buf, searched) {
+                break pos;
+            }
+            if self.buf.len() > HEADERS_MAX_LENGTH {
+                return Err(Error::HeaderToBig);
+            }
+            searched = self.buf.len();
+            if self.fill_buf().await.or(Err(Error::HeaderIncomplete))? == 0 {
+                return Err(Error::HeaderIncomplete);
+            }
+        };
+        if head_len > HEADERS_MAX_LENGTH {
+            return Err(Error::HeaderToBig);
+        }
+        let response = Response::from_header(&self.buf[..head_len])?;
+        self.buf.advance(head_len);
+        Ok(response)
+    }
+
+    pub async fn get_response(&mut self) -> Result<Response, Error> {
+        let mut response = self.get_head().await?;
+        response.body = self.read_body(&response).await?;
+        Ok(response)
+    }
+
+    /// Reads the body following the head of `response`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "body",
+            level = "debug",
+            skip_all,
+            fields(bytes = tracing::field::Empty),
+            err(Display)
+        )
+    )]
+    pub async fn read_body(&mut self, response: &Response) -> Result<Bytes, Error> {
+        let body = match (
+            response.has_body(),
+            response.has_chuncked_body(),
+            response.content_len(),
+        ) {
+            (true, false, Some(size)) => self.get_body(size).await?,
+            (true, true, _) => self.get_chunked_body().await?,
+            _ => Bytes::new(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("bytes", body.len());
+        Ok(body)
+    }
+
+    pub async fn get_body(&mut self, content_len: usize) -> Result<Bytes, Error> {
+        match self.download.clone() {
+            Some(callback) => {
+                self.buf
+                    .reserve(content_len.saturating_sub(self.buf.len()).min(MAX_RESERVE));
+                loop {
+                    let received = self.buf.len().min(content_len);
+                    self.report(&callback, received, Some(content_len));
+                    if received == content_len {
+                        break;
+                    }
+                    if self.fill_buf().await? == 0 {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                    }
+                }
+            }
+            None => self.fill_to(content_len).await?,
+        }
+        Ok(self.buf.split_to(content_len).freeze())
+    }
+
+    pub async fn read_chunk_line(&mut self) -> Result<usize, Error> {
+        let line = self.read_line().await?;
+        let without_ext = line
+            .split(|b| *b == b';')
+            .next()
+            .ok_or(Error::InvalidChunkSize)?;
+        let str_line = std::str::from_utf8(without_ext)?;
+        let size = usize::from_str_radix(str_line.trim(), 16)?;
+        Ok(size)
+    }
+
+    pub async fn get_chunked_body(&mut self) -> Result<Bytes, Error> {
+        let mut body = BytesMut::new();
+        loop {
+            match self.read_chunk_line().await? {
+                0 => break,
+                size => {
+                    self.fill_to(size.checked_add(2).ok_or(Error::InvalidChunkSize)?)
+                        .await?;
+                    let chunk = self.buf.split_to(size);
+                    if body.is_empty() {
+                        body = chunk;
+                    } else {
+                        body.extend_from_slice(&chunk);
+                    }
+                    if self.buf[..2] != b"\r\n"[..] {
+                        return Err(Error::InvalidChunkEOL);
+                    }
+                    self.buf.advance(2);
+                    if let Some(callback) = &self.download {
+                        self.report(callback, body.len(), None);
+                    }
+                }
+            }
+        }
+        while !self.read_line().await?.is_empty() {}
+        Ok(body.freeze())
+    }
+
+    /// Consumes the CRLF ending a chunk of a chunked body.
+    pub(crate) async fn read_chunk_end(&mut self) -> Result<(), Error> {
+        self.fill_to(2).await?;
+        if self.buf[..2] != b"\r\n"[..] {
+            return Err(Error::InvalidChunkEOL);
+        }
+        self.buf.advance(2);
+        Ok(())
+    }
+
+    /// Consumes the trailer section after the last chunk.
+    pub(crate) async fn skip_trailers(&mut self) -> Result<(), Error> {
+        while !self.read_line().await?.is_empty() {}
+        Ok(())
+    }
+
+    async fn read_line(&mut self) -> Result<Bytes, Error> {
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = self.buf[searched..].windows(2).position(|w| w == b"\r\n") {
+                let line = self.buf.split_to(searched + pos).freeze();
+                self.buf.advance(2);
+                return Ok(line);
+            }
+            if self.buf.len() >= CHUNK_MAX_LINE_LENGTH {
+                return Err(Error::ChunkLineTooLong(self.buf.len()));
+            }
+            searched = self.buf.len().saturating_sub(1);
+            if self.fill_buf().await? == 0 {
+                return Err(Error::InvalidChunkEOL);
+            }
+        }
+    }
+}
+
+impl BufStream<HttpStream> {
+    pub fn set_nodelay(&mut self, nodelay: bool) -> Result<(), Error> {
+        self.inner.set_nodelay(nodelay)
+    }
+}
+
+/// Returns the length of the response head including the empty line, or
+/// `None` when the head is not complete yet. Bytes before `from` were already
+/// searched by a previous call.
+pub(crate) fn find_head_end(buf: &[u8], from: usize) -> Option<usize> {
+    let start = from.saturating_sub(3);
+    buf[start..]
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| start + pos + 4)
+}
+
+impl<S: fmt::Debug> fmt::Debug for BufStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufStream")
+            .field("inner", &self.inner)
+            .field("buffered", &self.buf.len())
+            .finish()
+    }
+}
+
+impl<S> From<S> for BufStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn from(inner: S) -> Self {
+        BufStream::new(inner)
+    }
+}
+
+impl<S> AsyncRead for BufStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<Result<(), io::Error>> {
+        let this = Pin::get_mut(self);
+        if this.buf.is_empty() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let len = this.buf.len().min(buf.remaining());
+        buf.put_slice(&this.buf[..len]);
+        this.buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for BufStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut Pin::get_mut(self).inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut Pin::get_mut(self).inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut Pin::get_mut(self).inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+    use super::*;
+
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                              Content-Type: text/html\r\n\
+                              Content-Length: 5\r\n\r\n\
+                              helloEXTRA";
+    const CHUNKED: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                             Transfer-Encoding: chunked\r\n\r\n\
+                             5;ext=1\r\nhello\r\n\
+                             6\r\n world\r\n\
+                             0\r\n\
+                             Trailer: yes\r\n\r\n";
+
+    #[test]
+    fn head_end() {
+        assert_eq!(find_head_end(b"HTTP/1.1 200 OK\r\n\r\nbody", 0), Some(19));
+        assert_eq!(find_head_end(b"HTTP/1.1 200 OK\r\n\r\nbody", 17), Some(19));
+        assert_eq!(find_head_end(b"HTTP/1.1 200 OK\r\n", 0), None);
+        assert_eq!(find_head_end(b"", 0), None);
+    }
+
+    #[tokio::test]
+    async fn content_length_body_keeps_leftover() {
+        let (mut server, client) = duplex(64);
+        tokio::spawn(async move { server.write_all(RESPONSE).await.unwrap() });
+        let mut stream = BufStream::new(client);
+        let response = stream.get_response().await.unwrap();
+        assert_eq!(response.status_code().as_u16(), 200);
+        assert_eq!(&response.body()[..], b"hello");
+        let mut rest = [0u8; 5];
+        stream.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest, b"EXTRA");
+    }
+
+    #[tokio::test]
+    async fn chunked_body_with_trailer() {
+        let (mut server, client) = duplex(7);
+        tokio::spawn(async move { server.write_all(CHUNKED).await.unwrap() });
+        let mut stream = BufStream::new(client);
+        let response = stream.get_response().await.unwrap();
+        assert_eq!(&response.body()[..], b"hello world");
+        assert!(stream.buffer().is_empty());
+    }
+
+    #[tokio::test]
+    async fn oversized_chunk() {
+        let (mut server, client) = duplex(64);
+        tokio::spawn(async move {
+            server
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n",
+                )
+                .await
+                .unwrap()
+        });
+        let mut stream = BufStream::new(client);
+        assert_eq!(stream.get_response().await, Err(Error::InvalidChunkSize));
+
+        let (mut server, client) = duplex(64);
+        tokio::spawn(async move {
+            server
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nfffffffffffff\r\nabc",
+                )
+                .await
+                .unwrap()
+        });
+        let mut stream = BufStream::new(client);
+        let result = stream.get_response().await;
+        assert!(
+            matches!(result, Err(Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[tokio::test]
+    async fn incomplete_head() {
+        let (mut server, client) = duplex(64);
+        tokio::spawn(async move { server.write_all(b"HTTP/1.1 200 OK\r\n").await.unwrap() });
+        let mut stream = BufStream::new(client);
+        assert_eq!(stream.get_response().await, Err(Error::HeaderIncomplete));
+    }
+
+    #[tokio::test]
+    async fn head_too_big() {
+        let (mut server, client) = duplex(1024);
+        tokio::spawn(async move {
+            server.write_all(b"HTTP/1.1 200 OK\r\n").await.unwrap();
+            server.write_all(&[b'a'; 8192]).await.unwrap();
+        });
+        let mut stream = BufStream::new(client);
+        assert_eq!(stream.get_response().await, Err(Error::HeaderToBig));
+    }
+
+    #[tokio::test]
+    async fn body_progress() {
+        let (mut server, client) = duplex(7);
+        tokio::spawn(async move {
+            server.write_all(RESPONSE).await.unwrap();
+            server.write_all(CHUNKED).await.unwrap();
+        });
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let mut stream = BufStream::new(client);
+        stream.set_progress(
+            None,
+            Some(ProgressFn::new(move |progress: Progress| {
+                seen.lock()
+                    .unwrap()
+                    .push((progress.transferred, progress.total))
+            })),
+        );
+        stream.get_response().await.unwrap();
+        let mut extra = [0u8; 5];
+        stream.read_exact(&mut extra).await.unwrap();
+        stream.get_response().await.unwrap();
+        let reports = reports.lock().unwrap();
+        let (sized, chunked) = reports.split_at(reports.len() - 2);
+        assert!(sized.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert_eq!(sized.last(), Some(&(5, Some(5))));
+        assert_eq!(chunked, [(5, None), (11, None)]);
+    }
+
+    #[tokio::test]
+    async fn upload_progress() {
+        let (client, mut server) = duplex(1 << 16);
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let mut stream = BufStream::new(client);
+        stream.set_progress(
+            Some(ProgressFn::new(move |progress: Progress| {
+                seen.lock().unwrap().push(progress.transferred)
+            })),
+            None,
+        );
+        let mut msg = b"POST / HTTP/1.1\r\n\r\n".to_vec();
+        msg.extend_from_slice(&[b'x'; 20000]);
+        stream.send_request(&msg, 20000).await.unwrap();
+        let mut received = vec![0u8; msg.len()];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, msg);
+        assert_eq!(*reports.lock().unwrap(), [16384, 20000]);
+    }
+}
@@ -0,0 +1,669 @@
+// HTTP caching https://www.rfc-editor.org/rfc/rfc9111
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    fs,
+    path::{Path, PathBuf},
+    str,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use sha2::{Digest, Sha256};
+
+use crate::{Client, Error, Method, Request, Response, buf_stream::find_head_end};
+
+/// Statuses that may be stored without explicit freshness, RFC 9110 section 15.1.
+const HEURISTIC_STATUSES: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+const DEFAULT_MAX_ENTRIES: usize = 1024;
+/// Conditional headers of a request, which the cache leaves to the caller.
+const CONDITIONAL_HEADERS: [&str; 2] = ["If-None-Match", "If-Modified-Since"];
+
+/// How a request uses the cache, like the `cache` option of `fetch`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Serves fresh responses, revalidates stale ones and stores new ones.
+    #[default]
+    Default,
+    /// Bypasses the cache, neither reading nor storing.
+    NoStore,
+    /// Serves any stored response, even a stale one, and fetches otherwise.
+    ForceCache,
+    /// Serves any stored response and fails with [`Error::NotCached`]
+    /// instead of sending the request.
+    OnlyIfCached,
+}
+
+/// Backend keeping serialized cache entries by key.
+pub trait CacheStorage: Send + Sync {
+    fn get(&self, key: &str) -> Option<Bytes>;
+    fn put(&self, key: &str, value: Bytes);
+    fn remove(&self, key: &str);
+}
+
+/// Storage in a map that lives as long as the cache. It keeps at most
+/// `max_entries` keys, 1024 by default, and drops the least recently used one
+/// to make room for another.
+#[derive(Debug)]
+pub struct MemoryStorage {
+    entries: Mutex<MemoryEntries>,
+    max_entries: usize,
+}
+
+#[derive(Debug, Default)]
+struct MemoryEntries {
+    /// Values with the time they were last used.
+    map: HashMap<String, (Bytes, u64)>,
+    /// Counts the uses, ordering the entries by last use.
+    clock: u64,
+}
+
+impl MemoryStorage {
+    pub fn new(max_entries: usize) -> MemoryStorage {
+        MemoryStorage {
+            entries: Mutex::default(),
+            max_entries: max_entries.max(1),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MemoryEntries> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        MemoryStorage::new(DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl CacheStorage for MemoryStorage {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        let entries = &mut *self.lock();
+        entries.clock += 1;
+        let (value, used) = entries.map.get_mut(key)?;
+        *used = entries.clock;
+        Some(value.clone())
+    }
+
+    fn put(&self, key: &str, value: Bytes) {
+        let entries = &mut *self.lock();
+        entries.clock += 1;
+        if !entries.map.contains_key(key)
+            && entries.map.len() >= self.max_entries
+            && let Some(oldest) = entries
+                .map
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone())
+        {
+            entries.map.remove(&oldest);
+        }
+        entries.map.insert(key.to_string(), (value, entries.clock));
+    }
+
+    fn remove(&self, key: &str) {
+        self.lock().map.remove(key);
+    }
+}
+
+/// Storage with one file per key in a directory. Write errors are ignored,
+/// the entry is then fetched again next time. Nothing is ever evicted: the
+/// directory grows with every URL stored until it is cleaned up from the
+/// outside.
+#[derive(Debug)]
+pub struct DiskStorage {
+    dir: PathBuf,
+}
+
+impl DiskStorage {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<DiskStorage, Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(DiskStorage {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let hash = Sha256::digest(key.as_bytes());
+        let name = hash.iter().fold(String::new(), |mut name, b| {
+            let _ = write!(name, "{b:02x}");
+            name
+        });
+        self.dir.join(name)
+    }
+}
+
+impl CacheStorage for DiskStorage {
+    fn get(&self, key: &str) -> Option<Bytes> {
+        fs::read(self.path(key)).ok().map(Bytes::from)
+    }
+
+    fn put(&self, key: &str, value: Bytes) {
+        // Written aside and renamed, so readers never see a partial file.
+        let path = self.path(key);
+        let partial = path.with_extension("partial");
+        if fs::write(&partial, &value).is_ok() && fs::rename(&partial, &path).is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.path(key));
+    }
+}
+
+/// Private HTTP cache shared by the clients it is given to.
+///
+/// `GET` responses are stored when `Cache-Control`, `Expires` or their status
+/// allow it, with one variant per value of the request headers named in
+/// `Vary`. Stale responses are revalidated with `If-None-Match` and
+/// `If-Modified-Since`, and a `304 Not Modified` refreshes the stored one.
+/// Requests that carry their own conditional headers are sent as they are
+/// and get the response of the server. Successful unsafe requests remove the
+/// stored responses of their URL.
+#[derive(Clone)]
+pub struct Cache(Arc<dyn CacheStorage>);
+
+impl Cache {
+    pub fn new<S: CacheStorage + 'static>(storage: S) -> Cache {
+        Cache(Arc::new(storage))
+    }
+
+    pub fn memory() -> Cache {
+        Cache::new(MemoryStorage::default())
+    }
+
+    /// Cache persisted in `dir`, which is created when missing.
+    pub fn disk<P: AsRef<Path>>(dir: P) -> Result<Cache, Error> {
+        Ok(Cache::new(DiskStorage::new(dir)?))
+    }
+
+    /// Answers the request of `client` from the cache or the network.
+    pub(crate) async fn fetch(&self, client: &mut Client) -> Result<Response, Error> {
+        let mode = client.config.cache_mode;
+        if mode == CacheMode::NoStore {
+            return client.exchange().await;
+        }
+        let key = key(&client.request);
+        if client.request.method != Method::Get {
+            let response = client.exchange().await?;
+            let code = response.status_code();
+            if !matches!(
+                client.request.method,
+                Method::Head | Method::Options | Method::Trace
+            ) && (code.is_success() || code.is_redirect())
+            {
+                self.0.remove(&key);
+            }
+            return Ok(response);
+        }
+        let request_cc = request_cache_control(&client.request);
+        let mut entries = self.load(&key);
+        let found = entries
+            .iter()
+            .position(|entry| entry.matches(&client.request));
+        match found.map(|pos| &entries[pos]) {
+            Some(entry)
+                if mode != CacheMode::Default || entry.is_usable(&request_cc, unix_now()) =>
+            {
+                return Ok(entry.served(unix_now()));
+            }
+            None if mode == CacheMode::OnlyIfCached => return Err(Error::NotCached),
+            _ => {}
+        }
+        let conditional = CONDITIONAL_HEADERS
+            .iter()
+            .any(|name| client.request.headers.get(name).is_some());
+        let validators = match found {
+            Some(pos) if !conditional => entries[pos].validators(),
+            _ => Vec::new(),
+        };
+        for (name, value) in &validators {
+            client.request.header(name, value);
+        }
+        let request_time = unix_now();
+        let result = client.exchange().await;
+        for (name, _) in &validators {
+            client.request.remove_header(name);
+        }
+        let response = result?;
+        let response_time = unix_now();
+        if let Some(pos) = found
+            && !validators.is_empty()
+            && response.status_code().as_u16() == 304
+        {
+            entries[pos].refresh(&response, request_time, response_time);
+            let mut served = entries[pos].served(response_time);
+            served.timings = response.timings;
+            self.save(&key, &entries);
+            return Ok(served);
+        }
+        if is_storable(&client.request, &request_cc, &response) {
+            if let Some(pos) = found {
+                entries.remove(pos);
+            }
+            entries.push(Entry::new(
+                &client.request,
+                response.clone(),
+                request_time,
+                response_time,
+            ));
+            self.save(&key, &entries);
+        }
+        Ok(response)
+    }
+
+    fn load(&self, key: &str) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        if let Some(mut buf) = self.0.get(key) {
+            while let Some(entry) = Entry::decode(&mut buf) {
+                entries.push(entry);
+            }
+        }
+        entries
+    }
+
+    fn save(&self, key: &str, entries: &[Entry]) {
+        let mut buf = BytesMut::new();
+        for entry in entries {
+            entry.encode(&mut buf);
+        }
+        self.0.put(key, buf.freeze());
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Cache").finish()
+    }
+}
+
+impl PartialEq for Cache {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Cache {}
+
+/// A stored response with the times, in seconds since the epoch, of the
+/// exchange that produced it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    response: Response,
+    request_time: u64,
+    response_time: u64,
+    /// Request headers named by `Vary` that were present.
+    vary: Vec<(String, String)>,
+}
+
+impl Entry {
+    fn new(request: &Request, response: Response, request_time: u64, response_time: u64) -> Entry {
+        let vary = vary_names(&response)
+            .into_iter()
+            .filter_map(|name| Some((name.clone(), request.headers.get(&name)?)))
+            .collect();
+        Entry {
+            response,
+            request_time,
+            response_time,
+            vary,
+        }
+    }
+
+    /// Whether the request headers named by `Vary` match the stored ones.
+    fn matches(&self, request: &Request) -> bool {
+        vary_names(&self.response).into_iter().all(|name| {
+            let stored = self.vary.iter().find(|(stored, _)| *stored == name);
+            name != "*" && request.headers.get(&name).as_deref() == stored.map(|(_, v)| v.as_str())
+        })
+    }
+
+    fn cache_control(&self) -> CacheControl {
+        CacheControl::parse(&self.response.header("Cache-Control").unwrap_or_default())
+    }
+
+    fn date(&self) -> u64 {
+        self.response
+            .header("Date")
+            .and_then(|date| http_date(&date))
+            .unwrap_or(self.response_time)
+    }
+
+    /// Freshness lifetime in seconds, RFC 9111 section 4.2.1.
+    fn lifetime(&self) -> u64 {
+        if let Some(max_age) = self.cache_control().max_age {
+            return max_age;
+        }
+        let date = self.date();
+        if let Some(expires) = self.response.header("Expires") {
+            return http_date(&expires).map_or(0, |expires| expires.saturating_sub(date));
+        }
+        match self.response.header("Last-Modified") {
+            Some(modified)
+                if HEURISTIC_STATUSES.contains(&self.response.status_code().as_u16()) =>
+            {
+                http_date(&modified).map_or(0, |modified| date.saturating_sub(modified) / 10)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Current age in seconds, RFC 9111 section 4.2.3.
+    fn age(&self, now: u64) -> u64 {
+        let age_value = self
+            .response
+            .header("Age")
+            .and_then(|age| age.trim().parse().ok())
+            .unwrap_or(0u64);
+        let apparent_age = self.response_time.saturating_sub(self.date());
+        let response_delay = self.response_time.saturating_sub(self.request_time);
+        let initial_age = apparent_age.max(age_value.saturating_add(response_delay));
+        initial_age + now.saturating_sub(self.response_time)
+    }
+
+    /// Whether the response may be served without revalidation.
+    fn is_usable(&self, request_cc: &CacheControl, now: u64) -> bool {
+        let response_cc = self.cache_control();
+        if request_cc.no_cache || response_cc.no_cache {
+            return false;
+        }
+        let (age, lifetime) = (self.age(now), self.lifetime());
+        if request_cc.max_age.is_some_and(|max_age| age > max_age)
+            || request_cc
+                .min_fresh
+                .is_some_and(|min_fresh| age.saturating_add(min_fresh) > lifetime)
+        {
+            return false;
+        }
+        age < lifetime
+            || (!response_cc.must_revalidate
+                && request_cc
+                    .max_stale
+                    .is_some_and(|max_stale| age - lifetime <= max_stale))
+    }
+
+    fn served(&self, now: u64) -> Response {
+        let mut response = self.response.clone();
+        response.headers.insert("Age", &self.age(now));
+        response
+    }
+
+    /// Conditional request headers revalidating the response.
+    fn validators(&self) -> Vec<(&'static str, String)> {
+        let mut validators = Vec::new();
+        if let Some(etag) = self.response.header("ETag") {
+            validators.push(("If-None-Match", etag));
+        }
+        if let Some(modified) = self.response.header("Last-Modified") {
+            validators.push(("If-Modified-Since", modified));
+        }
+        validators
+    }
+
+    /// Updates the stored response with the headers of a `304` answering its
+    /// revalidation.
+    fn refresh(&mut self, not_modified: &Response, request_time: u64, response_time: u64) {
+        for (name, value) in not_modified.headers.iter() {
+            if !matches!(name.as_str(), "content-length" | "transfer-encoding") {
+                self.response.headers.insert(name, value);
+            }
+        }
+        self.request_time = request_time;
+        self.response_time = response_time;
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        let response = &self.response;
+        let mut head = format!(
+            "{} {} {}\r\n",
+            self.request_time,
+            self.response_time,
+            response.body.len()
+        );
+        for (name, value) in &self.vary {
+            let _ = write!(head, "{name}: {value}\r\n");
+        }
+        let _ = write!(
+            head,
+            "\r\n{} {} {}\r\n",
+            response.version(),
+            response.status_code(),
+            response.reason()
+        );
+        for (name, value) in response.headers.iter() {
+            let _ = write!(head, "{name}: {value}\r\n");
+        }
+        head.push_str("\r\n");
+        buf.put_slice(head.as_bytes());
+        buf.put_slice(&response.body);
+    }
+
+    fn decode(buf: &mut Bytes) -> Option<Entry> {
+        let meta_len = find_head_end(buf, 0)?;
+        let meta = str::from_utf8(&buf[..meta_len]).ok()?;
+        let mut lines = meta.lines();
+        let mut times = lines.next()?.split(' ').map(|n| n.parse::<u64>().ok());
+        let (request_time, response_time, body_len) =
+            (times.next()??, times.next()??, times.next()?? as usize);
+        let vary = lines
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        buf.advance(meta_len);
+        let head_len = find_head_end(buf, 0)?;
+        let mut response = Response::from_header(&buf[..head_len]).ok()?;
+        buf.advance(head_len);
+        if buf.len() < body_len {
+            return None;
+        }
+        response.body = buf.split_to(body_len);
+        Some(Entry {
+            response,
+            request_time,
+            response_time,
+            vary,
+        })
+    }
+}
+
+/// Directives of a `Cache-Control` header used by a private cache.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    must_revalidate: bool,
+    public: bool,
+    private: bool,
+    max_age: Option<u64>,
+    min_fresh: Option<u64>,
+    /// `u64::MAX` when `max-stale` has no value.
+    max_stale: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(value: &str) -> CacheControl {
+        let mut cc = CacheControl::default();
+        for directive in value.split(',') {
+            let (name, arg) = directive.split_once('=').unwrap_or((directive, ""));
+            let seconds = arg.trim().trim_matches('"').parse().ok();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                "must-revalidate" | "proxy-revalidate" => cc.must_revalidate = true,
+                "public" => cc.public = true,
+                "private" => cc.private = true,
+                // An invalid max-age makes the response stale.
+                "max-age" => cc.max_age = Some(seconds.unwrap_or(0)),
+                "min-fresh" => cc.min_fresh = seconds,
+                "max-stale" => cc.max_stale = Some(seconds.unwrap_or(u64::MAX)),
+                _ => {}
+            }
+        }
+        cc
+    }
+}
+
+fn request_cache_control(request: &Request) -> CacheControl {
+    match request.headers.get("Cache-Control") {
+        Some(value) => CacheControl::parse(&value),
+        None => CacheControl {
+            no_cache: request
+                .headers
+                .get("Pragma")
+                .is_some_and(|pragma| pragma.to_ascii_lowercase().contains("no-cache")),
+            ..CacheControl::default()
+        },
+    }
+}
+
+/// Whether a response may be stored, RFC 9111 section 3.
+fn is_storable(request: &Request, request_cc: &CacheControl, response: &Response) -> bool {
+    let code = response.status_code().as_u16();
+    let response_cc = CacheControl::parse(&response.header("Cache-Control").unwrap_or_default());
+    if request_cc.no_store
+        || response_cc.no_store
+        || matches!(code, 100..=199 | 206 | 304)
+        || vary_names(response).iter().any(|name| name == "*")
+    {
+        return false;
+    }
+    if request.headers.get("Authorization").is_some()
+        && !(response_cc.public || response_cc.must_revalidate)
+    {
+        return false;
+    }
+    response_cc.public
+        || response_cc.private
+        || response_cc.max_age.is_some()
+        || response.header("Expires").is_some()
+        || HEURISTIC_STATUSES.contains(&code)
+}
+
+/// Lowercased header names of the `Vary` header.
+fn vary_names(response: &Response) -> Vec<String> {
+    let vary = response.header("Vary").unwrap_or_default();
+    vary.split(',')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn key(request: &Request) -> String {
+    let mut url = request.url.clone();
+    url.set_fragment(None);
+    url.to_string()
+}
+
+fn unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+fn unix_now() -> u64 {
+    unix(SystemTime::now())
+}
+
+fn http_date(value: &str) -> Option<u64> {
+    httpdate::parse_http_date(value.trim()).ok().map(unix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATE: &str = "Sat, 11 Jan 2003 02:44:04 GMT";
+    const NOW: u64 = 1_042_253_044;
+
+    fn entry(head: &str) -> Entry {
+        let response = Response::from_header(head.as_bytes()).unwrap();
+        Entry {
+            response,
+            request_time: NOW,
+            response_time: NOW,
+            vary: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn memory_eviction() {
+        let storage = MemoryStorage::new(2);
+        storage.put("a", Bytes::from("1"));
+        storage.put("b", Bytes::from("2"));
+        assert!(storage.get("a").is_some());
+        storage.put("c", Bytes::from("3"));
+        assert_eq!(storage.get("b"), None);
+        assert_eq!(storage.get("a"), Some(Bytes::from("1")));
+        storage.put("a", Bytes::from("4"));
+        assert_eq!(storage.get("c"), Some(Bytes::from("3")));
+        assert_eq!(storage.get("a"), Some(Bytes::from("4")));
+    }
+
+    #[test]
+    fn parse_cache_control() {
+        let cc = CacheControl::parse("max-age=\"60\", No-Cache, private, max-stale");
+        assert_eq!(cc.max_age, Some(60));
+        assert!(cc.no_cache && cc.private && !cc.public);
+        assert_eq!(cc.max_stale, Some(u64::MAX));
+        assert_eq!(CacheControl::parse("max-age=soon").max_age, Some(0));
+        assert_eq!(CacheControl::parse(""), CacheControl::default());
+    }
+
+    #[test]
+    fn freshness() {
+        assert_eq!(http_date(DATE), Some(NOW));
+        let entry = entry(&format!(
+            "HTTP/1.1 200 OK\r\nDate: {DATE}\r\nAge: 30\r\nCache-Control: max-age=100\r\n\r\n"
+        ));
+        assert_eq!(entry.lifetime(), 100);
+        assert_eq!(entry.age(NOW + 10), 40);
+        let none = CacheControl::default();
+        assert!(entry.is_usable(&none, NOW + 69));
+        assert!(!entry.is_usable(&none, NOW + 70));
+        let max_stale = CacheControl::parse("max-stale=5");
+        assert!(entry.is_usable(&max_stale, NOW + 75));
+        let max_age = CacheControl::parse("max-age=20");
+        assert!(!entry.is_usable(&max_age, NOW));
+    }
+
+    #[test]
+    fn expires_and_heuristic() {
+        let expires = entry(&format!(
+            "HTTP/1.1 200 OK\r\nDate: {DATE}\r\nExpires: Sat, 11 Jan 2003 02:54:04 GMT\r\n\r\n"
+        ));
+        assert_eq!(expires.lifetime(), 600);
+        let invalid = entry(&format!(
+            "HTTP/1.1 200 OK\r\nDate: {DATE}\r\nExpires: 0\r\n\r\n"
+        ));
+        assert_eq!(invalid.lifetime(), 0);
+        let modified = entry(&format!(
+            "HTTP/1.1 200 OK\r\nDate: {DATE}\r\nLast-Modified: Sat, 11 Jan 2003 00:04:04 GMT\r\n\r\n"
+        ));
+        assert_eq!(modified.lifetime(), 960);
+    }
+
+    #[test]
+    fn vary_and_encoding() {
+        let url = "http://example.com/".parse().unwrap();
+        let mut request = Request::new(Method::Get, &url);
+        request.header("Accept-Language", "de");
+        let mut response = Response::from_header(
+            b"HTTP/1.1 200 OK\r\nVary: Accept-Language, Accept-Encoding\r\nContent-Length: 4\r\n",
+        )
+        .unwrap();
+        response.body = Bytes::from_static(b"body");
+        let entry = Entry::new(&request, response, NOW, NOW + 1);
+        assert!(entry.matches(&request));
+        let mut buf = BytesMut::new();
+        entry.encode(&mut buf);
+        entry.encode(&mut buf);
+        let mut buf = buf.freeze();
+        assert_eq!(Entry::decode(&mut buf), Some(entry.clone()));
+        assert_eq!(Entry::decode(&mut buf), Some(entry.clone()));
+        assert_eq!(Entry::decode(&mut buf), None);
+        request.header("Accept-Encoding", "gzip");
+        assert!(!entry.matches(&request));
+    }
+}
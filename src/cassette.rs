@@ -0,0 +1,384 @@
+// Record and replay of exchanges, like Ruby's VCR
+use std::{
+    fmt::{self, Write},
+    fs,
+    path::{Path, PathBuf},
+    str,
+    sync::{Arc, Mutex},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use url::Url;
+
+use crate::{
+    Client, Error, Headers, Method, Request, Response, buf_stream::find_head_end,
+    headers::SECRET_HEADERS,
+};
+
+/// When a [`Cassette`] goes to the network.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Replays a matching exchange, and records the request when there is none.
+    #[default]
+    Auto,
+    /// Sends every request and records it, replacing the exchanges recorded
+    /// before.
+    Record,
+    /// Never connects; requests without a matching exchange fail with
+    /// [`Error::NotRecorded`].
+    Replay,
+}
+
+/// Parts of a request compared to find its recorded exchange.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Matcher {
+    Method,
+    /// URL without its fragment and password.
+    Url,
+    Path,
+    Query,
+    Body,
+    /// Value of a header, after redaction.
+    Header(String),
+}
+
+impl Matcher {
+    fn matches(&self, request: &Request, recorded: &Request) -> bool {
+        match self {
+            Matcher::Method => request.method == recorded.method,
+            Matcher::Url => request.url == recorded.url,
+            Matcher::Path => request.url.path() == recorded.url.path(),
+            Matcher::Query => request.url.query() == recorded.url.query(),
+            Matcher::Body => {
+                request.body.clone().unwrap_or_default()
+                    == recorded.body.clone().unwrap_or_default()
+            }
+            Matcher::Header(name) => request.headers.get(name) == recorded.headers.get(name),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Interaction {
+    request: Request,
+    response: Response,
+    played: bool,
+}
+
+#[derive(Debug)]
+struct Tape {
+    path: PathBuf,
+    mode: CassetteMode,
+    matchers: Vec<Matcher>,
+    /// Lowercase names of the headers whose values are not recorded.
+    redact: Vec<String>,
+    interactions: Vec<Interaction>,
+}
+
+/// File of recorded exchanges, replayed instead of going to the network.
+///
+/// Requests are matched by method, URL and body unless other matchers are
+/// set, and each recorded exchange is replayed once before exchanges are
+/// repeated. The values of `Authorization`, `Proxy-Authorization`, `Cookie`,
+/// `Set-Cookie` and other redacted headers are replaced before they are
+/// written. Streaming requests and upgrades are neither recorded nor
+/// replayed. Cloning the cassette is cheap and all clones share the
+/// recording.
+#[derive(Clone)]
+pub struct Cassette(Arc<Mutex<Tape>>);
+
+impl Cassette {
+    /// Loads the exchanges recorded in `path`, if it exists. New exchanges
+    /// are written to it as they are recorded.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Cassette, Error> {
+        let path = path.as_ref().to_path_buf();
+        let interactions = match fs::read(&path) {
+            Ok(data) => decode(Bytes::from(data)).ok_or(Error::InvalidCassette)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Cassette(Arc::new(Mutex::new(Tape {
+            path,
+            mode: CassetteMode::Auto,
+            matchers: vec![Matcher::Method, Matcher::Url, Matcher::Body],
+            redact: SECRET_HEADERS.map(String::from).to_vec(),
+            interactions,
+        }))))
+    }
+
+    pub fn mode(self, mode: CassetteMode) -> Cassette {
+        {
+            let mut tape = self.lock();
+            tape.mode = mode;
+            if mode == CassetteMode::Record {
+                tape.interactions.clear();
+            }
+        }
+        self
+    }
+
+    /// Replaces the default matchers: method, URL and body.
+    pub fn match_on(self, matchers: &[Matcher]) -> Cassette {
+        self.lock().matchers = matchers.to_vec();
+        self
+    }
+
+    /// Keeps the value of header `name` out of the recording, in requests and
+    /// responses.
+    pub fn redact(self, name: &str) -> Cassette {
+        self.lock().redact.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Number of recorded exchanges.
+    pub fn len(&self) -> usize {
+        self.lock().interactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) async fn fetch(&self, client: &mut Client) -> Result<Response, Error> {
+        let (mode, request) = {
+            let mut tape = self.lock();
+            let request = tape.redact_request(&client.request);
+            if tape.mode != CassetteMode::Record
+                && let Some(response) = tape.play(&request)
+            {
+                return Ok(response);
+            }
+            (tape.mode, request)
+        };
+        if mode == CassetteMode::Replay {
+            return Err(Error::NotRecorded(format!(
+                "{} {}",
+                request.method, request.url
+            )));
+        }
+        let response = client.exchange().await?;
+        let mut tape = self.lock();
+        let recorded = Interaction {
+            request,
+            response: tape.redact_response(&response),
+            played: true,
+        };
+        tape.interactions.push(recorded);
+        tape.save()?;
+        Ok(response)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Tape> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Tape {
+    /// Response of the first matching exchange not replayed yet, or else of
+    /// the first matching one.
+    fn play(&mut self, request: &Request) -> Option<Response> {
+        let matches = |interaction: &Interaction| {
+            self.matchers
+                .iter()
+                .all(|matcher| matcher.matches(request, &interaction.request))
+        };
+        let pos = self
+            .interactions
+            .iter()
+            .position(|interaction| !interaction.played && matches(interaction))
+            .or_else(|| self.interactions.iter().position(matches))?;
+        let interaction = &mut self.interactions[pos];
+        interaction.played = true;
+        let mut response = interaction.response.clone();
+        response.method = request.method.clone();
+        Some(response)
+    }
+
+    fn redact_request(&self, request: &Request) -> Request {
+        let mut request = request.clone();
+        request.url.set_fragment(None);
+        let _ = request.url.set_password(None);
+        request.proxy = None;
+        request.headers.redact(&self.redact);
+        request
+    }
+
+    fn redact_response(&self, response: &Response) -> Response {
+        let mut response = response.clone();
+        response.headers.redact(&self.redact);
+        response
+    }
+
+    /// Writes the file aside and renames it, so it is never left partial.
+    fn save(&self) -> Result<(), Error> {
+        let mut buf = BytesMut::new();
+        for interaction in &self.interactions {
+            encode(interaction, &mut buf);
+        }
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, &buf)?;
+        fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Cassette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tape = self.lock();
+        f.debug_struct("Cassette")
+            .field("path", &tape.path)
+            .field("mode", &tape.mode)
+            .field("interactions", &tape.interactions.len())
+            .finish()
+    }
+}
+
+impl PartialEq for Cassette {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Cassette {}
+
+/// Writes a line with both body lengths, then the request and the response as
+/// they are sent on the wire, with the absolute URL as request target.
+fn encode(interaction: &Interaction, buf: &mut BytesMut) {
+    let (request, response) = (&interaction.request, &interaction.response);
+    let request_body = request.body.clone().unwrap_or_default();
+    let mut head = format!(
+        "{} {}\r\n{} {} {}\r\n",
+        request_body.len(),
+        response.body.len(),
+        request.method,
+        request.url,
+        request.version
+    );
+    write_headers(&mut head, &request.headers);
+    buf.put_slice(head.as_bytes());
+    buf.put_slice(&request_body);
+    let mut head = format!(
+        "{} {} {}\r\n",
+        response.version(),
+        response.status_code(),
+        response.reason()
+    );
+    write_headers(&mut head, &response.headers);
+    buf.put_slice(head.as_bytes());
+    buf.put_slice(&response.body);
+}
+
+fn write_headers(head: &mut String, headers: &Headers) {
+    for (name, value) in headers.iter() {
+        let _ = write!(head, "{name}: {value}\r\n");
+    }
+    head.push_str("\r\n");
+}
+
+fn decode(mut buf: Bytes) -> Option<Vec<Interaction>> {
+    let mut interactions = Vec::new();
+    while buf.has_remaining() {
+        let head_len = find_head_end(&buf, 0)?;
+        let head = str::from_utf8(&buf[..head_len]).ok()?;
+        let (lengths, head) = head.split_once("\r\n")?;
+        let (request_line, headers) = head.split_once("\r\n")?;
+        let mut lengths = lengths.split(' ').map(|n| n.parse::<usize>().ok());
+        let (request_len, response_len) = (lengths.next()??, lengths.next()??);
+        let mut parts = request_line.split(' ');
+        let (method, url, version) = (parts.next()?, parts.next()?, parts.next()?);
+        let mut request = Request::new(Method::from(method), &Url::parse(url).ok()?);
+        request.version = version.parse().ok()?;
+        request.headers = headers.parse().ok()?;
+        buf.advance(head_len);
+        if buf.len() < request_len {
+            return None;
+        }
+        request.body = Some(buf.split_to(request_len)).filter(|body| !body.is_empty());
+        let head_len = find_head_end(&buf, 0)?;
+        let mut response = Response::from_header(&buf[..head_len]).ok()?;
+        buf.advance(head_len);
+        if buf.len() < response_len {
+            return None;
+        }
+        response.body = buf.split_to(response_len);
+        interactions.push(Interaction {
+            request,
+            response,
+            played: false,
+        });
+    }
+    Some(interactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::REDACTED;
+
+    fn interaction(method: Method, url: &str, body: &str) -> Interaction {
+        let mut request = Request::new(method.clone(), &Url::parse(url).unwrap());
+        request.header("Authorization", "Basic c2VjcmV0");
+        request.body = Some(Bytes::from(body.to_string())).filter(|body| !body.is_empty());
+        let mut response = Response::from_header(b"HTTP/1.1 200 OK\r\nX-Id: 1\r\n\r\n").unwrap();
+        response.body = Bytes::from(format!("{method} {url}"));
+        Interaction {
+            request,
+            response,
+            played: false,
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let recorded = [
+            interaction(Method::Post, "http://a.com/x?q=1", "a\r\n\r\nb"),
+            interaction(Method::Get, "http://a.com/", ""),
+        ];
+        let mut buf = BytesMut::new();
+        for interaction in &recorded {
+            encode(interaction, &mut buf);
+        }
+        let decoded = decode(buf.freeze()).unwrap();
+        assert_eq!(decoded.len(), 2);
+        for (decoded, recorded) in decoded.iter().zip(&recorded) {
+            assert_eq!(decoded.request.url, recorded.request.url);
+            assert_eq!(decoded.request.method, recorded.request.method);
+            assert_eq!(decoded.request.headers, recorded.request.headers);
+            assert_eq!(decoded.request.body, recorded.request.body);
+            assert_eq!(decoded.response, recorded.response);
+        }
+        assert!(
+            decode(Bytes::from_static(
+                b"3 0\r\nGET http://a.com/ HTTP/1.1\r\n\r\nab"
+            ))
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn matching() {
+        let mut tape = Tape {
+            path: PathBuf::new(),
+            mode: CassetteMode::Replay,
+            matchers: vec![Matcher::Method, Matcher::Url, Matcher::Body],
+            redact: vec!["authorization".into()],
+            interactions: vec![
+                interaction(Method::Get, "http://a.com/", ""),
+                interaction(Method::Get, "http://a.com/", ""),
+                interaction(Method::Post, "http://a.com/", "x"),
+            ],
+        };
+        tape.interactions[1].response.body = Bytes::from_static(b"second");
+        let get = tape.redact_request(&interaction(Method::Get, "http://u:p@a.com/#f", "").request);
+        assert_eq!(get.url.as_str(), "http://u@a.com/");
+        assert_eq!(get.headers.get("Authorization").as_deref(), Some(REDACTED));
+        let get = interaction(Method::Get, "http://a.com/", "").request;
+        assert_eq!(tape.play(&get).unwrap().body, "GET http://a.com/");
+        assert_eq!(tape.play(&get).unwrap().body, "second");
+        assert_eq!(tape.play(&get).unwrap().body, "GET http://a.com/");
+        let post = interaction(Method::Post, "http://a.com/", "y").request;
+        assert!(tape.play(&post).is_none());
+        tape.matchers = vec![Matcher::Method, Matcher::Path];
+        assert!(tape.play(&post).is_some());
+    }
+}
@@ -0,0 +1,219 @@
+// HTTP Authentication challenges https://datatracker.ietf.org/doc/html/rfc7235#section-4.1
+use std::collections::HashMap;
+
+/// A challenge of a `WWW-Authenticate` or `Proxy-Authenticate` header.
+///
+/// Parameter names are lowercased. The `realm` parameter is kept in its own
+/// field and is not repeated in `params`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Challenge {
+    pub scheme: String,
+    pub realm: Option<String>,
+    pub params: HashMap<String, String>,
+    pub token68: Option<String>,
+}
+
+impl Challenge {
+    /// Parses a comma separated list of challenges. Malformed parts of the
+    /// list are skipped.
+    pub fn parse_list(header: &str) -> Vec<Challenge> {
+        let mut parser = Parser {
+            input: header,
+            pos: 0,
+        };
+        let mut challenges = Vec::new();
+        loop {
+            parser.skip(|c| c == ',' || is_space(c));
+            if parser.is_end() {
+                break;
+            }
+            let scheme = parser.take(is_tchar);
+            if scheme.is_empty() {
+                // Not a challenge, skip to the next list element.
+                parser.take(|c| c != ',');
+                continue;
+            }
+            let mut challenge = Challenge {
+                scheme: scheme.to_string(),
+                ..Challenge::default()
+            };
+            if parser.skip(is_space) > 0 {
+                challenge.token68 = parser.token68();
+                if challenge.token68.is_none() {
+                    while let Some((name, value)) = parser.auth_param() {
+                        if name == "realm" {
+                            challenge.realm = Some(value);
+                        } else {
+                            challenge.params.insert(name, value);
+                        }
+                    }
+                }
+            }
+            challenges.push(challenge);
+        }
+        challenges
+    }
+
+    /// Whether the challenge uses `scheme`, compared case-insensitively.
+    pub fn has_scheme(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn is_end(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn take(&mut self, accept: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !accept(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn skip(&mut self, accept: impl Fn(char) -> bool) -> usize {
+        self.take(accept).len()
+    }
+
+    /// Reads a `token68` credential when it is the whole challenge payload.
+    fn token68(&mut self) -> Option<String> {
+        let start = self.pos;
+        let token = self.take(is_token68_char);
+        let padding = self.take(|c| c == '=');
+        self.skip(is_space);
+        if !token.is_empty() && matches!(self.peek(), None | Some(',')) {
+            return Some(format!("{token}{padding}"));
+        }
+        self.pos = start;
+        None
+    }
+
+    /// Reads the next `name=value` parameter of the current challenge. Stops
+    /// without consuming input at the start of the next challenge.
+    fn auth_param(&mut self) -> Option<(String, String)> {
+        let start = self.pos;
+        self.skip(|c| c == ',' || is_space(c));
+        let name = self.take(is_tchar);
+        self.skip(is_space);
+        if name.is_empty() || self.peek() != Some('=') {
+            self.pos = start;
+            return None;
+        }
+        self.pos += 1;
+        self.skip(is_space);
+        let value = if self.peek() == Some('"') {
+            self.pos += 1;
+            self.quoted_string()
+        } else {
+            self.take(is_tchar).to_string()
+        };
+        self.skip(is_space);
+        Some((name.to_ascii_lowercase(), value))
+    }
+
+    fn quoted_string(&mut self) -> String {
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        let mut end = self.rest().len();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        value.push(escaped);
+                    }
+                }
+                '"' => {
+                    end = idx + 1;
+                    break;
+                }
+                c => value.push(c),
+            }
+        }
+        self.pos += end;
+        value
+    }
+}
+
+fn is_space(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+fn is_token68_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~+/".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_single() {
+        let challenges = Challenge::parse_list(r#"Basic realm="WallyWorld", charset="UTF-8""#);
+        assert_eq!(challenges.len(), 1);
+        assert_eq!(challenges[0].scheme, "Basic");
+        assert_eq!(challenges[0].realm.as_deref(), Some("WallyWorld"));
+        assert_eq!(challenges[0].param("Charset"), Some("UTF-8"));
+        assert_eq!(challenges[0].param("realm"), None);
+    }
+
+    // RFC 7235 section 4.1
+    #[test]
+    fn parse_multiple_schemes() {
+        let challenges = Challenge::parse_list(
+            r#"Newauth realm="apps", type=1, title="Login to \"apps\"", Basic realm="simple""#,
+        );
+        assert_eq!(challenges.len(), 2);
+        assert!(challenges[0].has_scheme("newauth"));
+        assert_eq!(challenges[0].realm.as_deref(), Some("apps"));
+        assert_eq!(challenges[0].param("type"), Some("1"));
+        assert_eq!(challenges[0].param("title"), Some(r#"Login to "apps""#));
+        assert!(challenges[1].has_scheme("Basic"));
+        assert_eq!(challenges[1].realm.as_deref(), Some("simple"));
+    }
+
+    #[test]
+    fn parse_token68() {
+        let challenges = Challenge::parse_list("Negotiate YIIBhgYGKwYBBQUCoII+/w==, Bearer");
+        assert_eq!(challenges.len(), 2);
+        assert_eq!(
+            challenges[0].token68.as_deref(),
+            Some("YIIBhgYGKwYBBQUCoII+/w==")
+        );
+        assert!(challenges[0].params.is_empty());
+        assert!(challenges[1].has_scheme("bearer"));
+        assert_eq!(challenges[1].token68, None);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        let challenges = Challenge::parse_list(r#", "junk", Digest nonce = "n" ,,  realm=x"#);
+        assert_eq!(challenges.len(), 1);
+        assert_eq!(challenges[0].param("nonce"), Some("n"));
+        assert_eq!(challenges[0].realm.as_deref(), Some("x"));
+        assert!(Challenge::parse_list("").is_empty());
+    }
+}
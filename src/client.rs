@@ -1,15 +1,29 @@
+use std::time::{Duration, Instant, SystemTime};
+
 use bytes::Bytes;
 use futures::{FutureExt, future::BoxFuture};
 use url::Url;
 
-use crate::{ClientBuilder, Error, Headers, HttpStream, Request, Response, client_builder::Config};
+use crate::{
+    AuthCache, Body, BufStream, ClientBuilder, Error, HarEntry, Headers, HttpStream, Method,
+    Request, RequestEvent, Response, Timings,
+    bearer::is_invalid_token,
+    client_builder::{Config, connect},
+    digest::{Credentials, DigestChallenge, auth_key, proxy_auth_key},
+};
+
+const MAX_AUTH_RETRIES: usize = 3;
 
 #[derive(Debug)]
 pub struct Client {
     pub(crate) request: Request,
-    pub(crate) stream: HttpStream,
+    /// Connection of the request, opened on first use when it is deferred.
+    pub(crate) stream: Option<BufStream<HttpStream>>,
     pub(crate) response: Option<Response>,
     pub(crate) config: Config,
+    /// Timings of opening the connection, until the first exchange on it
+    /// takes them over.
+    pub(crate) setup: Option<Timings>,
 }
 
 impl Client {
@@ -25,17 +39,55 @@ impl Client {
     ) -> Client {
         Client {
             request,
-            stream,
+            stream: Some(BufStream::new(stream)),
             response,
             config,
+            setup: None,
         }
     }
 
+    /// Client that connects when the request is first sent.
+    pub(crate) fn deferred(request: Request, config: Config) -> Client {
+        Client {
+            request,
+            stream: None,
+            response: None,
+            config,
+            setup: None,
+        }
+    }
+
+    async fn stream(&mut self) -> Result<&mut BufStream<HttpStream>, Error> {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => {
+                let (stream, setup) = connect(&self.request, &self.config).await?;
+                self.setup = Some(setup);
+                BufStream::new(stream)
+            }
+        };
+        Ok(self.stream.insert(stream))
+    }
+
     pub fn send(&mut self) -> BoxFuture<'_, Result<Response, Error>> {
         async {
-            self.stream.send_msg(&self.request.to_vec()).await?;
-            let mut response = self.stream.get_response().await?;
+            let start = Instant::now();
+            let result = self.fetch().await;
+            self.report_proxy(&result);
+            self.observe(result.as_ref(), start.elapsed());
+            let mut response = result?;
             response.method = self.request.method.clone();
+            if self.token_rejected(&response) {
+                if let Some(provider) = &self.config.token_provider {
+                    provider.0.refresh().await?;
+                }
+                self.config.token_refreshed = true;
+                return ClientBuilder::from_client(self).build().await?.send().await;
+            }
+            if self.digest_challenge(&response) {
+                self.config.auth_retries += 1;
+                return ClientBuilder::from_client(self).build().await?.send().await;
+            }
             if response.status_code().is_redirect()
                 && let Some(location) = response.headers().get("Location")
             {
@@ -47,12 +99,24 @@ impl Client {
                     current_url
                 };
                 self.redirect()?;
-                return ClientBuilder::from_client(self)
-                    .url(&redirect_url)
-                    .build()
-                    .await?
-                    .send()
-                    .await;
+                #[cfg(feature = "tracing")]
+                let span = tracing::info_span!(
+                    "redirect",
+                    hop = self.config.redirects,
+                    status = response.status_code().as_u16(),
+                    location = %crate::trace::url(&redirect_url),
+                );
+                let next = async {
+                    ClientBuilder::from_client(self)
+                        .redirect_to(&redirect_url)
+                        .build()
+                        .await?
+                        .send()
+                        .await
+                };
+                #[cfg(feature = "tracing")]
+                let next = tracing::Instrument::instrument(next, span);
+                return next.await;
             };
             self.response = Some(response.clone());
             Ok(response)
@@ -60,6 +124,252 @@ impl Client {
         .boxed()
     }
 
+    /// Sends the request and returns the response head together with the
+    /// body, which is read incrementally from the connection. Redirects and
+    /// authentication challenges are not followed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "request",
+            skip_all,
+            fields(
+                method = %self.request.method,
+                url = %crate::trace::url(&self.request.url),
+                status = tracing::field::Empty,
+            ),
+            err(Display)
+        )
+    )]
+    pub async fn send_streaming(mut self) -> Result<(Response, Body), Error> {
+        let (started, start) = (SystemTime::now(), Instant::now());
+        let mut timings = Timings::default();
+        let result = self.send_head(&mut timings).await;
+        self.record(started, timings, result.as_ref());
+        self.observe(result.as_ref(), start.elapsed());
+        let response = result?;
+        let stream = self.stream.take().ok_or(Error::EmptyResponse)?;
+        let body = Body::new(stream, &response);
+        Ok((response, body))
+    }
+
+    /// Sends the request and hands over the connection once the server
+    /// switched protocols with `101`, or accepted a `CONNECT` with `2xx`.
+    /// Bytes the server sent after the response are kept in the returned
+    /// [`BufStream`] and read first.
+    pub async fn upgrade(mut self) -> Result<(Response, BufStream<HttpStream>), Error> {
+        let mut response = self.exchange().await?;
+        response.method = self.request.method.clone();
+        let code = response.status_code();
+        let switched = match self.request.method {
+            Method::Connect => code.is_success(),
+            _ => code.as_u16() == 101,
+        };
+        if !switched {
+            return Err(Error::UpgradeRefused(code.as_u16()));
+        }
+        let stream = self.stream.take().ok_or(Error::EmptyResponse)?;
+        Ok((response, stream))
+    }
+
+    /// Answers the request from the cassette or the cache when one is
+    /// configured.
+    async fn fetch(&mut self) -> Result<Response, Error> {
+        match (self.config.cassette.clone(), self.config.cache.clone()) {
+            (Some(cassette), _) => cassette.fetch(self).await,
+            (None, Some(cache)) => cache.fetch(self).await,
+            (None, None) => self.exchange().await,
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "request",
+            skip_all,
+            fields(
+                method = %self.request.method,
+                url = %crate::trace::url(&self.request.url),
+                status = tracing::field::Empty,
+            ),
+            err(Display)
+        )
+    )]
+    pub(crate) async fn exchange(&mut self) -> Result<Response, Error> {
+        let started = SystemTime::now();
+        let mut timings = Timings::default();
+        let result = async {
+            let mut response = self.send_head(&mut timings).await?;
+            let start = Instant::now();
+            response.body = self.stream().await?.read_body(&response).await?;
+            timings.transfer = start.elapsed();
+            timings.total += timings.transfer;
+            response.timings = timings;
+            Ok(response)
+        }
+        .await;
+        self.record(started, timings, result.as_ref());
+        result
+    }
+
+    /// Sends the request and reads the response head, timing each phase.
+    async fn send_head(&mut self, timings: &mut Timings) -> Result<Response, Error> {
+        let stream = self.stream().await?.get_ref();
+        let reused = Timings {
+            remote_addr: stream.peer_addr().ok(),
+            local_addr: stream.local_addr().ok(),
+            reused: true,
+            ..Timings::default()
+        };
+        *timings = self.setup.take().unwrap_or(reused);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(headers = %crate::trace::headers(&self.request.headers), "sending request");
+        let start = Instant::now();
+        self.send_request().await?;
+        timings.send = start.elapsed();
+        let mut response = self.stream().await?.get_head().await?;
+        timings.first_byte = start.elapsed();
+        timings.total += timings.first_byte;
+        response.method = self.request.method.clone();
+        response.timings = *timings;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("status", response.status_code().as_u16());
+        Ok(response)
+    }
+
+    /// Adds the exchange to the HAR recorder.
+    pub(crate) fn record(
+        &self,
+        started: SystemTime,
+        timings: Timings,
+        result: Result<&Response, &Error>,
+    ) {
+        let Some(har) = &self.config.har else {
+            return;
+        };
+        har.record(HarEntry {
+            started,
+            request: self.request.clone(),
+            response: result.cloned().map_err(Error::to_string),
+            timings,
+            proxy: self.proxy(),
+        });
+    }
+
+    /// Reports the outcome to the metrics observer.
+    pub(crate) fn observe(&self, result: Result<&Response, &Error>, duration: Duration) {
+        let Some(observer) = &self.config.observer else {
+            return;
+        };
+        let proxy = self.proxy().map(|mut proxy| {
+            let _ = proxy.set_username("");
+            let _ = proxy.set_password(None);
+            proxy
+        });
+        observer.0.on_request(&RequestEvent {
+            host: self.request.url.host_str().unwrap_or_default().to_string(),
+            method: self.request.method.clone(),
+            status: result.ok().map(|response| response.status_code().as_u16()),
+            error: result.err().map(Error::kind),
+            bytes_sent: self.request.body.as_ref().map_or(0, Bytes::len),
+            bytes_received: result.map_or(0, |response| response.body.len()),
+            duration,
+            proxy,
+        });
+    }
+
+    /// Proxy of the request, the first hop for a proxy chain.
+    fn proxy(&self) -> Option<Url> {
+        self.request
+            .proxy
+            .clone()
+            .or_else(|| self.config.proxy_chain.first().cloned())
+    }
+
+    async fn send_request(&mut self) -> Result<(), Error> {
+        let msg = self.request.to_vec();
+        let body_len = self.request.body.as_ref().map_or(0, Bytes::len);
+        let (upload, download) = (
+            self.config.upload_progress.clone(),
+            self.config.download_progress.clone(),
+        );
+        let stream = self.stream().await?;
+        stream.set_progress(upload, download);
+        stream.send_request(&msg, body_len).await
+    }
+
+    fn token_rejected(&self, response: &Response) -> bool {
+        self.config.token_provider.is_some()
+            && !self.config.token_refreshed
+            && response.status_code().as_u16() == 401
+            && response.challenges().iter().any(is_invalid_token)
+    }
+
+    /// Stores a `Digest` challenge of a `401` or `407` response when the
+    /// request can be repeated with credentials answering it.
+    fn digest_challenge(&mut self, response: &Response) -> bool {
+        let (challenges, auth_header, key, credentials) = match response.status_code().as_u16() {
+            401 => (
+                response.challenges(),
+                "Authorization",
+                auth_key(&self.request.url),
+                self.config
+                    .credentials
+                    .clone()
+                    .or_else(|| Credentials::from_url(&self.request.url)),
+            ),
+            407 if self.request.is_forwarded() => match &self.request.proxy {
+                Some(proxy) => (
+                    response.proxy_challenges(),
+                    "Proxy-Authorization",
+                    proxy_auth_key(proxy),
+                    Credentials::from_url(proxy),
+                ),
+                None => return false,
+            },
+            _ => return false,
+        };
+        let Some(challenge) = DigestChallenge::find(&challenges) else {
+            return false;
+        };
+        let rejected = self
+            .request
+            .headers
+            .get(auth_header)
+            .is_some_and(|value| value.contains(&format!("nonce=\"{}\"", challenge.nonce)));
+        if credentials.is_none()
+            || (rejected && !challenge.stale)
+            || self.config.auth_retries >= MAX_AUTH_RETRIES
+        {
+            return false;
+        }
+        self.config
+            .auth_cache
+            .get_or_insert_with(AuthCache::new)
+            .store(&key, challenge);
+        true
+    }
+
+    /// Reports the outcome to the proxy pool with the time taken to connect
+    /// through the proxy, unless the response came from the cache without a
+    /// connection.
+    fn report_proxy(&self, result: &Result<Response, Error>) {
+        if let (Some(pool), Some(proxy), Some(_)) =
+            (&self.config.proxy_pool, &self.request.proxy, &self.stream)
+        {
+            match result {
+                Ok(response) if response.status_code().as_u16() != 407 => {
+                    let timings = response.timings();
+                    if timings.reused {
+                        pool.report_reused(proxy)
+                    } else {
+                        pool.report_success(proxy, timings.dns + timings.connect + timings.proxy)
+                    }
+                }
+                _ => pool.report_failure(proxy),
+            }
+        }
+    }
+
     pub fn body(&self) -> Option<Bytes> {
         self.request.get_body()
     }
@@ -91,8 +401,8 @@ mod tests {
     use super::*;
     use crate::tests::ip_str;
 
-    const SIMPLE_URL: &'static str = "http://api.ipify.org";
-    const SECURE_URL: &'static str = "https://api.ipify.org";
+    const SIMPLE_URL: &str = "http://api.ipify.org";
+    const SECURE_URL: &str = "https://api.ipify.org";
 
     #[tokio::test]
     async fn client_https() {
@@ -1,9 +1,24 @@
-use std::{convert::TryInto, time::Duration};
+use std::{
+    convert::TryInto,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use bytes::Bytes;
-use url::Url;
-
-use crate::{Client, Error, Headers, HttpStream, Method, Request, Version, utils::IntoUrl};
+use url::{Origin, Url};
+
+use crate::{
+    AuthCache, BandwidthLimiter, Cache, CacheMode, Cassette, Client, Download, EnvProxy, Error,
+    EventSource, HarRecorder, Headers, HttpStream, Method, MetricsObserver, Netrc, Progress,
+    ProxyPool, RateLimiter, Request, Throttled, Timings, TokenProvider, Version, WebSocket,
+    bearer::TokenSource,
+    digest::{Credentials, auth_key, proxy_auth_key},
+    observer::Observer,
+    progress::ProgressFn,
+    utils::IntoUrl,
+    websocket::generate_key,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Config {
@@ -12,6 +27,30 @@ pub struct Config {
     pub connect_timeout: Option<Duration>,
     pub redirects: usize,
     pub max_redirects: usize,
+    pub env_proxy: Option<EnvProxy>,
+    pub proxy_pool: Option<ProxyPool>,
+    pub proxy_chain: Vec<Url>,
+    pub auth_cache: Option<AuthCache>,
+    pub auth_retries: usize,
+    pub token_refreshed: bool,
+    pub netrc: Option<Netrc>,
+    /// Why the `.netrc` file couldn't be read, reported by `build`.
+    pub(crate) netrc_error: Option<String>,
+    pub(crate) credentials: Option<Credentials>,
+    pub(crate) token_provider: Option<TokenSource>,
+    /// Origin of the first request, the only one given provider tokens and
+    /// the `default` credentials of the `.netrc` file.
+    pub(crate) first_origin: Option<Origin>,
+    pub cache: Option<Cache>,
+    pub cache_mode: CacheMode,
+    pub cassette: Option<Cassette>,
+    pub rate_limiter: Option<RateLimiter>,
+    pub download_limit: Option<BandwidthLimiter>,
+    pub upload_limit: Option<BandwidthLimiter>,
+    pub(crate) upload_progress: Option<ProgressFn>,
+    pub(crate) download_progress: Option<ProgressFn>,
+    pub har: Option<HarRecorder>,
+    pub(crate) observer: Option<Observer>,
 }
 
 impl Config {
@@ -22,6 +61,27 @@ impl Config {
             connect_timeout: None,
             redirects: 0,
             max_redirects: 10,
+            env_proxy: None,
+            proxy_pool: None,
+            proxy_chain: Vec::new(),
+            auth_cache: None,
+            auth_retries: 0,
+            token_refreshed: false,
+            netrc: None,
+            netrc_error: None,
+            credentials: None,
+            token_provider: None,
+            first_origin: None,
+            cache: None,
+            cache_mode: CacheMode::Default,
+            cassette: None,
+            rate_limiter: None,
+            download_limit: None,
+            upload_limit: None,
+            upload_progress: None,
+            download_progress: None,
+            har: None,
+            observer: None,
         }
     }
 }
@@ -32,7 +92,7 @@ impl Default for Config {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ClientBuilder {
     pub(crate) url: Option<Url>,
     pub(crate) headers: Headers,
@@ -70,24 +130,147 @@ impl ClientBuilder {
             method: client.request.method.clone(),
             version: client.request.version,
             body: client.request.body.clone(),
-            proxy: client.request.proxy.clone(),
+            proxy: match &client.config.env_proxy {
+                Some(env_proxy)
+                    if env_proxy.proxy_for(&client.request.url) == client.request.proxy =>
+                {
+                    None
+                }
+                _ => client.request.proxy.clone(),
+            },
             config: client.config.clone(),
         }
     }
 
-    pub async fn build(self) -> Result<Client, Error> {
+    /// Builder for `request` with the settings of `template`.
+    pub fn from_request(template: &ClientBuilder, request: Request) -> Self {
+        ClientBuilder {
+            url: Some(request.url),
+            headers: request.headers,
+            method: request.method,
+            version: request.version,
+            body: request.body,
+            proxy: request.proxy.or_else(|| template.proxy.clone()),
+            config: template.config.clone(),
+        }
+    }
+
+    pub async fn build(mut self) -> Result<Client, Error> {
         let url = self.url.ok_or(Error::EmptyUrl)?;
+        if let Some(err) = &self.config.netrc_error {
+            return Err(Error::Netrc(err.clone()));
+        }
+        if let Some(limiter) = &self.config.rate_limiter {
+            limiter.acquire(url.host_str().unwrap_or_default()).await;
+        }
         let mut request = Request::new(Method::Get, &url);
-        request.proxy(self.proxy.as_ref());
         request.headers(self.headers);
         request.method(self.method);
         request.version(self.version);
         request.opt_body(self.body);
-        let mut stream = HttpStream::from_request(&request).await?;
-        if self.config.nodelay {
-            stream.set_nodelay(true)?;
+        let first_origin = self.config.first_origin.get_or_insert_with(|| url.origin());
+        let same_origin = *first_origin == url.origin();
+        if same_origin && let Some(provider) = &self.config.token_provider {
+            let token = provider.0.token().await?;
+            request.header("Authorization", &format!("Bearer {token}"));
+        }
+        // After a redirect to another origin only an entry naming the new host
+        // is used, not the `default` one.
+        let netrc_credentials = self.config.netrc.as_ref().and_then(|netrc| {
+            if same_origin {
+                netrc.credentials(&url)
+            } else {
+                netrc.machine_credentials(&url)
+            }
+        });
+        if let Some(credentials) = netrc_credentials
+            && request.headers.get("Authorization").is_none()
+            && matches!(url.scheme(), "http" | "https")
+        {
+            request.set_basic_auth(&credentials.username, &credentials.password);
+        }
+        if self.config.proxy_chain.is_empty() {
+            let proxy = match (self.proxy, &self.config.proxy_pool, &self.config.env_proxy) {
+                (Some(proxy), _, _) => Some(proxy),
+                (None, Some(pool), _) => Some(pool.next_proxy()?),
+                (None, None, Some(env_proxy)) => env_proxy.proxy_for(&url),
+                (None, None, None) => None,
+            };
+            request.proxy(proxy.as_ref());
+        } else {
+            request.proxy(None);
+        }
+        apply_digest(&self.config, &mut request);
+        // Keeps the Digest challenges of proxies answered while tunneling for
+        // the next requests of the client.
+        if request
+            .proxy
+            .iter()
+            .chain(&self.config.proxy_chain)
+            .any(|proxy| proxy.password().is_some())
+        {
+            self.config.auth_cache.get_or_insert_with(AuthCache::new);
+        }
+        // With a cache or a cassette the connection is opened only when the
+        // response can't be served from it.
+        if self.config.cache.is_some() || self.config.cassette.is_some() {
+            return Ok(Client::deferred(request, self.config));
+        }
+        let (started, start) = (SystemTime::now(), Instant::now());
+        let (stream, setup) = match connect(&request, &self.config).await {
+            Ok(connection) => connection,
+            Err(err) => {
+                let timings = Timings {
+                    total: start.elapsed(),
+                    ..Timings::default()
+                };
+                let client = Client::deferred(request, self.config);
+                client.record(started, timings, Err(&err));
+                client.observe(Err(&err), timings.total);
+                return Err(err);
+            }
         };
-        Ok(Client::new(request, stream, None, self.config))
+        let mut client = Client::new(request, stream, None, self.config);
+        client.setup = Some(setup);
+        Ok(client)
+    }
+
+    /// Asks the server to switch to `protocol` with `Upgrade`. Take over the
+    /// connection with [`Client::upgrade`].
+    pub fn upgrade(self, protocol: &str) -> ClientBuilder {
+        self.header("Upgrade", protocol)
+            .header("Connection", "Upgrade")
+    }
+
+    /// Opens a WebSocket connection to a `ws` or `wss` URL with the HTTP/1.1
+    /// Upgrade handshake. Proxies are used like for other requests; HTTP
+    /// proxies are asked for a CONNECT tunnel.
+    pub async fn websocket(mut self) -> Result<WebSocket, Error> {
+        let key = generate_key();
+        self.body = None;
+        let client = self
+            .method(Method::Get)
+            .version(Version::Http11)
+            .header_remove("Content-Type")
+            .upgrade("websocket")
+            .header("Sec-WebSocket-Key", &key)
+            .header("Sec-WebSocket-Version", "13")
+            .build()
+            .await?;
+        let (response, stream) = client.upgrade().await?;
+        WebSocket::handshake(stream, &key, &response)
+    }
+
+    /// Streams the response body into the file at `path`, resuming after
+    /// interruptions. The download starts when the returned value is awaited.
+    pub fn download_to<P: AsRef<Path>>(self, path: P) -> Download {
+        Download::new(self, path)
+    }
+
+    /// Subscribes to a `text/event-stream` endpoint. The request is sent when
+    /// the returned stream is first polled and again on every reconnect.
+    pub fn event_source(self) -> EventSource {
+        EventSource::new(self)
     }
 
     pub fn url<U: IntoUrl>(mut self, value: U) -> ClientBuilder {
@@ -98,6 +281,17 @@ impl ClientBuilder {
         self
     }
 
+    /// Points the builder at the target of a redirect. Credentials and the
+    /// Host of the previous origin don't follow a redirect to another origin.
+    pub(crate) fn redirect_to(mut self, url: &Url) -> ClientBuilder {
+        if self.url.as_ref().map(Url::origin) != Some(url.origin()) {
+            self.headers.remove("Host");
+            self.headers.remove("Authorization");
+        }
+        self.url = Some(url.clone());
+        self
+    }
+
     pub fn proxy<P: IntoUrl>(mut self, value: P) -> ClientBuilder {
         match value.into_url() {
             Ok(url) => self.proxy = Some(url),
@@ -106,6 +300,84 @@ impl ClientBuilder {
         self
     }
 
+    /// Picks the proxy from `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and
+    /// `NO_PROXY` for every target, including redirect hops. A proxy set with
+    /// [`ClientBuilder::proxy`] takes precedence.
+    pub fn proxy_from_env(mut self) -> ClientBuilder {
+        self.config.env_proxy = Some(EnvProxy::from_env());
+        self
+    }
+
+    /// Sends a fixed `Authorization: Bearer` token with the request.
+    pub fn bearer_auth<T: ToString + ?Sized>(self, token: &T) -> ClientBuilder {
+        self.header("Authorization", &format!("Bearer {}", token.to_string()))
+    }
+
+    /// Asks `provider` for a bearer token before each request and refreshes it
+    /// once when the server answers `401` with an `invalid_token` error.
+    /// Redirects to another origin are sent without the token.
+    pub fn token_provider(mut self, provider: Arc<dyn TokenProvider>) -> ClientBuilder {
+        self.config.token_provider = Some(TokenSource(provider));
+        self
+    }
+
+    /// Sends Basic credentials of the `.netrc` file at `path` to matching hosts
+    /// when no `Authorization` header was set. The `default` entry is only
+    /// used for the origin of the first request, not after a redirect to
+    /// another one. `build` fails when the file can't be read.
+    pub fn netrc<P: AsRef<Path>>(mut self, path: P) -> ClientBuilder {
+        match Netrc::from_file(&path) {
+            Ok(netrc) => {
+                self.config.netrc = Some(netrc);
+                self.config.netrc_error = None;
+            }
+            Err(err) => {
+                let reason = match &err {
+                    Error::Io(io) => io.to_string(),
+                    _ => err.to_string(),
+                };
+                self.config.netrc_error = Some(format!("{}: {reason}", path.as_ref().display()));
+            }
+        }
+        self
+    }
+
+    /// Credentials answering `Digest` challenges of the server. Without them
+    /// the user info of the request URL is used.
+    pub fn digest_auth(mut self, username: &str, password: &str) -> ClientBuilder {
+        self.config.credentials = Some(Credentials::new(username, password));
+        self
+    }
+
+    /// Shares received `Digest` nonces with other clients using the same cache.
+    pub fn auth_cache(mut self, cache: &AuthCache) -> ClientBuilder {
+        self.config.auth_cache = Some(cache.clone());
+        self
+    }
+
+    /// Tunnels through every proxy of `chain` in order before reaching the
+    /// target. Hops may mix `socks4`, `socks4a`, `socks5`, `socks5h`, `http`
+    /// and `https` proxies and take credentials from their URLs. A chain
+    /// takes precedence over any other proxy setting.
+    pub fn proxy_chain<I, P>(mut self, chain: I) -> ClientBuilder
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoUrl,
+    {
+        self.config.proxy_chain = chain
+            .into_iter()
+            .filter_map(|proxy| proxy.into_url().ok())
+            .collect();
+        self
+    }
+
+    /// Draws the proxy from `pool` when no proxy was set explicitly. Failures
+    /// to connect through it and `407` responses are reported back to the pool.
+    pub fn proxy_pool(mut self, pool: &ProxyPool) -> ClientBuilder {
+        self.config.proxy_pool = Some(pool.clone());
+        self
+    }
+
     pub fn headers(mut self, headers: Headers) -> ClientBuilder {
         for (key, value) in headers.iter() {
             self.headers.insert(key, &value);
@@ -232,6 +504,79 @@ impl ClientBuilder {
         self
     }
 
+    /// Answers requests from `cache` and stores their responses in it. The
+    /// connection is opened only when the network is needed.
+    pub fn cache(mut self, cache: &Cache) -> ClientBuilder {
+        self.config.cache = Some(cache.clone());
+        self
+    }
+
+    pub fn cache_mode(mut self, mode: CacheMode) -> ClientBuilder {
+        self.config.cache_mode = mode;
+        self
+    }
+
+    /// Replays the exchanges recorded in `cassette` and records new ones,
+    /// depending on its mode. It is used instead of the cache.
+    pub fn cassette(mut self, cassette: &Cassette) -> ClientBuilder {
+        self.config.cassette = Some(cassette.clone());
+        self
+    }
+
+    /// Records every request and response of the client, redirect hops and
+    /// retries included, into `recorder`.
+    pub fn har(mut self, recorder: &HarRecorder) -> ClientBuilder {
+        self.config.har = Some(recorder.clone());
+        self
+    }
+
+    /// Reports every request to `observer`, including redirect hops, retries,
+    /// streamed requests and connections that could not be opened.
+    pub fn observer(mut self, observer: Arc<dyn MetricsObserver>) -> ClientBuilder {
+        self.config.observer = Some(Observer(observer));
+        self
+    }
+
+    /// Waits for `limiter` before every request, including redirects and
+    /// retries, so requests to each host stay under its rate.
+    pub fn rate_limiter(mut self, limiter: &RateLimiter) -> ClientBuilder {
+        self.config.rate_limiter = Some(limiter.clone());
+        self
+    }
+
+    /// Limits the bytes per second read from the connection.
+    pub fn download_limit(mut self, limiter: &BandwidthLimiter) -> ClientBuilder {
+        self.config.download_limit = Some(limiter.clone());
+        self
+    }
+
+    /// Limits the bytes per second written to the connection.
+    pub fn upload_limit(mut self, limiter: &BandwidthLimiter) -> ClientBuilder {
+        self.config.upload_limit = Some(limiter.clone());
+        self
+    }
+
+    /// Calls `callback` as the request body is written, with the body length
+    /// as total.
+    pub fn on_upload_progress<F>(mut self, callback: F) -> ClientBuilder
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.config.upload_progress = Some(ProgressFn::new(callback));
+        self
+    }
+
+    /// Calls `callback` as the response body is read, also through the
+    /// [`Body`](crate::Body) of a streamed response, with the `Content-Length`
+    /// as total when the server sent one.
+    pub fn on_download_progress<F>(mut self, callback: F) -> ClientBuilder
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.config.download_progress = Some(ProgressFn::new(callback));
+        self
+    }
+
     pub fn referer<U>(self, value: U) -> ClientBuilder
     where
         U: IntoUrl,
@@ -260,6 +605,92 @@ impl ClientBuilder {
     }
 }
 
+/// Opens the connection for `request`, through the proxy chain when one is
+/// configured, and reports the time each phase took.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "connect",
+        level = "debug",
+        skip_all,
+        fields(
+            url = %crate::trace::url(&request.url),
+            proxy = %request
+                .proxy
+                .as_ref()
+                .or(config.proxy_chain.first())
+                .map(crate::trace::url)
+                .unwrap_or_default(),
+        ),
+        err(Display)
+    )
+)]
+pub(crate) async fn connect(
+    request: &Request,
+    config: &Config,
+) -> Result<(HttpStream, Timings), Error> {
+    let start = Instant::now();
+    let mut timings = Timings::default();
+    let mut stream = if config.proxy_chain.is_empty() {
+        match HttpStream::open_request(request, config.auth_cache.as_ref(), &mut timings).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                if let (Some(pool), Some(proxy)) = (&config.proxy_pool, &request.proxy) {
+                    pool.report_failure(proxy);
+                }
+                return Err(err);
+            }
+        }
+    } else {
+        HttpStream::open_chain(
+            &config.proxy_chain,
+            &request.url,
+            config.auth_cache.as_ref(),
+            &mut timings,
+        )
+        .await?
+    };
+    if config.nodelay {
+        stream.set_nodelay(true)?;
+    };
+    timings.total = start.elapsed();
+    Ok((throttle(config, stream), timings))
+}
+
+/// Wraps the connection when bandwidth limits are configured.
+fn throttle(config: &Config, stream: HttpStream) -> HttpStream {
+    match (&config.download_limit, &config.upload_limit) {
+        (None, None) => stream,
+        (read, write) => Throttled::new(stream, read.clone(), write.clone()).into(),
+    }
+}
+
+/// Answers `Digest` challenges already cached for the server and the proxy.
+fn apply_digest(config: &Config, request: &mut Request) {
+    let Some(cache) = &config.auth_cache else {
+        return;
+    };
+    let uri = request.request_uri();
+    let credentials = config
+        .credentials
+        .clone()
+        .or_else(|| Credentials::from_url(&request.url));
+    if let Some(credentials) = credentials
+        && let Some(value) =
+            cache.authorization(&auth_key(&request.url), &credentials, &request.method, &uri)
+    {
+        request.header("Authorization", &value);
+    }
+    if request.is_forwarded()
+        && let Some(proxy) = &request.proxy
+        && let Some(credentials) = Credentials::from_url(proxy)
+        && let Some(value) =
+            cache.authorization(&proxy_auth_key(proxy), &credentials, &request.method, &uri)
+    {
+        request.header("Proxy-Authorization", &value);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // use super::*;
@@ -0,0 +1,347 @@
+// HTTP Digest Access Authentication https://datatracker.ietf.org/doc/html/rfc7616
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use md5::Md5;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{Challenge, Method};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl Algorithm {
+    fn parse(value: &str) -> Option<Algorithm> {
+        match value.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Algorithm::Md5),
+            "MD5-SESS" => Some(Algorithm::Md5Sess),
+            "SHA-256" => Some(Algorithm::Sha256),
+            "SHA-256-SESS" => Some(Algorithm::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Md5Sess => "MD5-sess",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn is_sess(&self) -> bool {
+        matches!(self, Algorithm::Md5Sess | Algorithm::Sha256Sess)
+    }
+
+    fn hash(&self, data: &str) -> String {
+        let digest = match self {
+            Algorithm::Md5 | Algorithm::Md5Sess => Md5::digest(data.as_bytes()).to_vec(),
+            Algorithm::Sha256 | Algorithm::Sha256Sess => Sha256::digest(data.as_bytes()).to_vec(),
+        };
+        digest.iter().fold(String::new(), |mut output, b| {
+            let _ = write!(output, "{b:02x}");
+            output
+        })
+    }
+}
+
+/// Parameters of a `Digest` challenge from `WWW-Authenticate` or
+/// `Proxy-Authenticate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DigestChallenge {
+    pub(crate) realm: String,
+    pub(crate) nonce: String,
+    pub(crate) opaque: Option<String>,
+    pub(crate) algorithm: Algorithm,
+    pub(crate) qop_auth: bool,
+    pub(crate) stale: bool,
+}
+
+impl DigestChallenge {
+    /// Finds the first supported `Digest` challenge.
+    pub(crate) fn find(challenges: &[Challenge]) -> Option<DigestChallenge> {
+        challenges
+            .iter()
+            .filter(|challenge| challenge.has_scheme("Digest"))
+            .find_map(DigestChallenge::from_challenge)
+    }
+
+    fn from_challenge(challenge: &Challenge) -> Option<DigestChallenge> {
+        let algorithm = match challenge.param("algorithm") {
+            Some(algorithm) => Algorithm::parse(algorithm)?,
+            None => Algorithm::Md5,
+        };
+        let qop_auth = match challenge.param("qop") {
+            Some(qop) => {
+                if !qop.split(',').any(|qop| qop.trim() == "auth") {
+                    return None;
+                }
+                true
+            }
+            None => false,
+        };
+        Some(DigestChallenge {
+            realm: challenge.realm.clone().unwrap_or_default(),
+            nonce: challenge.param("nonce")?.to_string(),
+            opaque: challenge.param("opaque").map(str::to_string),
+            algorithm,
+            qop_auth,
+            stale: challenge
+                .param("stale")
+                .is_some_and(|stale| stale.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    pub(crate) fn authorization(
+        &self,
+        credentials: &Credentials,
+        method: &Method,
+        uri: &str,
+        nc: u32,
+        cnonce: &str,
+    ) -> String {
+        let algorithm = self.algorithm;
+        let mut ha1 = algorithm.hash(&format!(
+            "{}:{}:{}",
+            credentials.username, self.realm, credentials.password
+        ));
+        if algorithm.is_sess() {
+            ha1 = algorithm.hash(&format!("{ha1}:{}:{cnonce}", self.nonce));
+        }
+        let ha2 = algorithm.hash(&format!("{method}:{uri}"));
+        let nc = format!("{nc:08x}");
+        let response = if self.qop_auth {
+            algorithm.hash(&format!("{ha1}:{}:{nc}:{cnonce}:auth:{ha2}", self.nonce))
+        } else {
+            algorithm.hash(&format!("{ha1}:{}:{ha2}", self.nonce))
+        };
+        let mut value = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{uri}\", algorithm={}, response=\"{response}\"",
+            credentials.username,
+            self.realm,
+            self.nonce,
+            algorithm.as_str()
+        );
+        if let Some(opaque) = &self.opaque {
+            let _ = write!(value, ", opaque=\"{opaque}\"");
+        }
+        if self.qop_auth {
+            let _ = write!(value, ", qop=auth, nc={nc}, cnonce=\"{cnonce}\"");
+        }
+        value
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Credentials {
+    pub(crate) username: String,
+    pub(crate) password: String,
+}
+
+impl Credentials {
+    pub(crate) fn new(username: &str, password: &str) -> Credentials {
+        Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    pub(crate) fn from_url(url: &Url) -> Option<Credentials> {
+        url.password()
+            .map(|password| Credentials::new(url.username(), password))
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+struct DigestSession {
+    challenge: DigestChallenge,
+    nc: u32,
+}
+
+/// Digest challenges received per server or proxy, reused to authenticate
+/// later requests without another round trip. Cloning the cache is cheap and
+/// all clones share the same nonces.
+#[derive(Clone, Debug, Default)]
+pub struct AuthCache(Arc<Mutex<HashMap<String, DigestSession>>>);
+
+impl AuthCache {
+    pub fn new() -> AuthCache {
+        AuthCache::default()
+    }
+
+    pub(crate) fn store(&self, key: &str, challenge: DigestChallenge) {
+        self.lock()
+            .insert(key.to_string(), DigestSession { challenge, nc: 0 });
+    }
+
+    /// Computes the next authorization value for `key`, counting the nonce use.
+    pub(crate) fn authorization(
+        &self,
+        key: &str,
+        credentials: &Credentials,
+        method: &Method,
+        uri: &str,
+    ) -> Option<String> {
+        let mut sessions = self.lock();
+        let session = sessions.get_mut(key)?;
+        session.nc += 1;
+        let cnonce = format!("{:016x}", rand::rng().random::<u64>());
+        Some(
+            session
+                .challenge
+                .authorization(credentials, method, uri, session.nc, &cnonce),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, DigestSession>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl PartialEq for AuthCache {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for AuthCache {}
+
+/// Cache key for the protection space of a server or proxy URL.
+pub(crate) fn auth_key(url: &Url) -> String {
+    format!(
+        "{}://{}:{}",
+        url.scheme(),
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+pub(crate) fn proxy_auth_key(proxy: &Url) -> String {
+    format!("proxy {}", auth_key(proxy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(header: &str) -> Option<DigestChallenge> {
+        DigestChallenge::find(&Challenge::parse_list(header))
+    }
+
+    #[test]
+    fn parse_challenge() {
+        let challenge = parse(
+            "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+             nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+             opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        )
+        .unwrap();
+        assert_eq!(challenge.realm, "testrealm@host.com");
+        assert_eq!(challenge.nonce, "dcd98b7102dd2f0e8b11d0f600bfb0c093");
+        assert_eq!(
+            challenge.opaque.as_deref(),
+            Some("5ccc069c403ebaf9f0171e9517f40e41")
+        );
+        assert_eq!(challenge.algorithm, Algorithm::Md5);
+        assert!(challenge.qop_auth);
+        assert!(!challenge.stale);
+    }
+
+    #[test]
+    fn parse_unsupported() {
+        assert!(parse("Basic realm=\"x\"").is_none());
+        assert!(parse("Digest realm=\"x\", nonce=\"y\", qop=\"auth-int\"").is_none());
+        assert!(parse("Digest realm=\"x\", nonce=\"y\", algorithm=SHA-512").is_none());
+    }
+
+    // RFC 2617 section 3.5
+    #[test]
+    fn rfc2617_md5() {
+        let challenge = parse(
+            "Digest realm=\"testrealm@host.com\", qop=\"auth\", \
+             nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+             opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        )
+        .unwrap();
+        let credentials = Credentials::new("Mufasa", "Circle Of Life");
+        let value =
+            challenge.authorization(&credentials, &Method::Get, "/dir/index.html", 1, "0a4f113b");
+        assert!(value.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(value.contains("nc=00000001"));
+    }
+
+    // RFC 7616 section 3.9.1
+    #[test]
+    fn rfc7616_sha256() {
+        let challenge = parse(
+            "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+             algorithm=SHA-256, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+        )
+        .unwrap();
+        let credentials = Credentials::new("Mufasa", "Circle of Life");
+        let value = challenge.authorization(
+            &credentials,
+            &Method::Get,
+            "/dir/index.html",
+            1,
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        );
+        assert!(value.contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+    }
+
+    #[test]
+    fn sess_algorithm() {
+        let challenge =
+            parse("Digest realm=\"r\", nonce=\"n\", algorithm=MD5-sess, qop=auth").unwrap();
+        let credentials = Credentials::new("u", "p");
+        let ha1 = Algorithm::Md5.hash(&format!("{}:n:c", Algorithm::Md5.hash("u:r:p")));
+        let ha2 = Algorithm::Md5.hash("GET:/");
+        let expected = Algorithm::Md5.hash(&format!("{ha1}:n:00000001:c:auth:{ha2}"));
+        let value = challenge.authorization(&credentials, &Method::Get, "/", 1, "c");
+        assert!(value.contains(&format!("response=\"{expected}\"")));
+        assert!(value.contains("algorithm=MD5-sess"));
+    }
+
+    #[test]
+    fn cache_counts_nonce() {
+        let cache = AuthCache::new();
+        let challenge = parse("Digest realm=\"r\", nonce=\"n\", qop=auth").unwrap();
+        cache.store("key", challenge);
+        let credentials = Credentials::new("u", "p");
+        let first = cache
+            .authorization("key", &credentials, &Method::Get, "/")
+            .unwrap();
+        let second = cache
+            .authorization("key", &credentials, &Method::Get, "/")
+            .unwrap();
+        assert!(first.contains("nc=00000001"));
+        assert!(second.contains("nc=00000002"));
+        assert!(
+            cache
+                .authorization("other", &credentials, &Method::Get, "/")
+                .is_none()
+        );
+    }
+}
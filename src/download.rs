@@ -0,0 +1,537 @@
+use std::{
+    future::IntoFuture,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+use url::Url;
+
+use crate::{ClientBuilder, Error, Progress, Response, progress::ProgressFn};
+
+const MAX_RETRIES: usize = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Download of a response body to a file, started by awaiting it.
+///
+/// When the connection breaks the download continues where it stopped with
+/// `Range: bytes=N-`, after a delay that doubles with every retry. The `ETag`
+/// or `Last-Modified` of the first response is sent in `If-Range`, so a
+/// changed file is downloaded again from the start, as is a file from a
+/// server that ignores ranges. Awaiting resolves to the size of the file.
+///
+/// The body is written beside the file with a `.partial` suffix and moved
+/// over it once complete, so a failed download leaves an existing file as
+/// it was. The validator is kept in a `.partial.validator` file, and a later
+/// download to the same path resumes a `.partial` file left by a failed
+/// single stream download.
+#[derive(Debug)]
+pub struct Download {
+    builder: ClientBuilder,
+    path: PathBuf,
+    max_retries: usize,
+    retry_delay: Duration,
+    segments: usize,
+    progress: Option<ProgressFn>,
+}
+
+enum Step {
+    Done,
+    Interrupted(Error),
+    Redirect(Url),
+}
+
+/// Part of the file fetched over one connection.
+struct State {
+    file: File,
+    offset: u64,
+    /// Last byte of a segment, `None` when the whole body is fetched.
+    end: Option<u64>,
+    total: Option<u64>,
+    validator: Option<String>,
+    /// File the validator is saved to, so a later download can resume.
+    validator_path: Option<PathBuf>,
+    meter: Option<Meter>,
+}
+
+/// Progress of all segments of a download.
+#[derive(Clone)]
+struct Meter {
+    callback: ProgressFn,
+    transferred: Arc<AtomicU64>,
+    start: Instant,
+}
+
+/// Length and validator of a body that may be fetched in ranges.
+struct Probe {
+    builder: ClientBuilder,
+    total: u64,
+    validator: Option<String>,
+}
+
+impl Download {
+    pub(crate) fn new<P: AsRef<Path>>(builder: ClientBuilder, path: P) -> Download {
+        Download {
+            builder,
+            path: path.as_ref().to_path_buf(),
+            max_retries: MAX_RETRIES,
+            retry_delay: RETRY_DELAY,
+            segments: 1,
+            progress: None,
+        }
+    }
+
+    /// Number of times an interrupted download is resumed, 3 by default.
+    /// Every segment is resumed on its own.
+    pub fn max_retries(mut self, max_retries: usize) -> Download {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled for every further one up to 30
+    /// seconds. 500 ms by default.
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Download {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Splits the body into `segments` byte ranges fetched concurrently over
+    /// separate connections. A server that does not answer a range request
+    /// with `Accept-Ranges: bytes` and the complete length in `Content-Range`,
+    /// or fails to answer it, is downloaded in a single stream.
+    pub fn segments(mut self, segments: usize) -> Download {
+        self.segments = segments.max(1);
+        self
+    }
+
+    /// Calls `callback` after every piece of the body written to the file.
+    pub fn on_progress<F>(mut self, callback: F) -> Download
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(ProgressFn::new(callback));
+        self
+    }
+
+    async fn run(self) -> Result<u64, Error> {
+        let partial = with_suffix(&self.path, ".partial");
+        let validator_path = with_suffix(&self.path, ".partial.validator");
+        match self.write(&partial, &validator_path).await {
+            Ok(size) => {
+                tokio::fs::rename(&partial, &self.path).await?;
+                let _ = tokio::fs::remove_file(&validator_path).await;
+                Ok(size)
+            }
+            Err(err) => {
+                // Keeps what was received for the next download, unless
+                // there is nothing to resume.
+                if tokio::fs::metadata(&partial)
+                    .await
+                    .is_ok_and(|metadata| metadata.len() == 0)
+                {
+                    let _ = tokio::fs::remove_file(&partial).await;
+                    let _ = tokio::fs::remove_file(&validator_path).await;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Downloads the body into the file at `path`, resuming it when it was
+    /// left by an earlier single stream download with a validator saved in
+    /// `validator_path`.
+    async fn write(&self, path: &Path, validator_path: &Path) -> Result<u64, Error> {
+        let meter = self.progress.clone().map(|callback| Meter {
+            callback,
+            transferred: Arc::new(AtomicU64::new(0)),
+            start: Instant::now(),
+        });
+        if let Some((file, offset, validator)) = resumable(path, validator_path).await {
+            if let Some(meter) = &meter {
+                meter.transferred.store(offset, Ordering::Relaxed);
+            }
+            let mut state = State {
+                file,
+                offset,
+                end: None,
+                total: None,
+                validator: Some(validator),
+                validator_path: Some(validator_path.to_path_buf()),
+                meter,
+            };
+            state
+                .run(self.builder.clone(), self.max_retries, self.retry_delay)
+                .await?;
+            return Ok(state.offset);
+        }
+        let file = File::create(path).await?;
+        if self.segments > 1
+            && let Some(probe) = probe(self.builder.clone()).await
+        {
+            // Segments can't be resumed by appending to the file.
+            let _ = tokio::fs::remove_file(validator_path).await;
+            file.set_len(probe.total).await?;
+            let segments = split(probe.total, self.segments)
+                .into_iter()
+                .map(|(first, last)| {
+                    let (builder, meter) = (probe.builder.clone(), meter.clone());
+                    let validator = probe.validator.clone();
+                    async move {
+                        let mut file = OpenOptions::new().write(true).open(path).await?;
+                        file.seek(std::io::SeekFrom::Start(first)).await?;
+                        let mut state = State {
+                            file,
+                            offset: first,
+                            end: Some(last),
+                            total: Some(probe.total),
+                            validator,
+                            validator_path: None,
+                            meter,
+                        };
+                        state.run(builder, self.max_retries, self.retry_delay).await
+                    }
+                });
+            futures::future::try_join_all(segments).await?;
+            return Ok(probe.total);
+        }
+        let mut state = State {
+            file,
+            offset: 0,
+            end: None,
+            total: None,
+            validator: None,
+            validator_path: Some(validator_path.to_path_buf()),
+            meter,
+        };
+        state.set_validator(None).await?;
+        state
+            .run(self.builder.clone(), self.max_retries, self.retry_delay)
+            .await?;
+        Ok(state.offset)
+    }
+}
+
+/// `path` with `suffix` added to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// The non-empty partial file at `path` opened for appending, with its length
+/// and the validator saved beside it. `None` when there is nothing that can be
+/// resumed safely.
+async fn resumable(path: &Path, validator_path: &Path) -> Option<(File, u64, String)> {
+    let validator = tokio::fs::read_to_string(validator_path).await.ok()?;
+    if validator.is_empty() {
+        return None;
+    }
+    let file = OpenOptions::new().append(true).open(path).await.ok()?;
+    let offset = file.metadata().await.ok()?.len();
+    (offset > 0).then_some((file, offset, validator))
+}
+
+impl IntoFuture for Download {
+    type Output = Result<u64, Error>;
+    type IntoFuture = BoxFuture<'static, Result<u64, Error>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.run().boxed()
+    }
+}
+
+/// Asks for the first byte of the body to learn whether the server serves
+/// ranges. `None` when the body has to be fetched in one piece, also when the
+/// request failed: the single stream download reports the error if it fails
+/// too.
+async fn probe(builder: ClientBuilder) -> Option<Probe> {
+    try_probe(builder).await.ok().flatten()
+}
+
+async fn try_probe(mut builder: ClientBuilder) -> Result<Option<Probe>, Error> {
+    let mut redirects = 0;
+    loop {
+        let url = builder.url.clone();
+        let client = builder.clone().header("Range", "bytes=0-0").build().await?;
+        let (response, _) = client.send_streaming().await?;
+        let code = response.status_code();
+        if code.is_redirect()
+            && let (Some(url), Some(location)) = (url, response.header("Location"))
+        {
+            redirects += 1;
+            if redirects >= builder.config.max_redirects {
+                return Err(Error::MaxRedirects);
+            }
+            builder = builder.redirect_to(&url.join(&location)?);
+            continue;
+        }
+        let ranges = response.header("Accept-Ranges").unwrap_or_default();
+        let total = response
+            .header("Content-Range")
+            .and_then(|value| ContentRange::parse(&value))
+            .and_then(|range| range.length);
+        return Ok(match total {
+            Some(total)
+                if code.as_u16() == 206
+                    && total > 0
+                    && ranges
+                        .split(',')
+                        .any(|unit| unit.trim().eq_ignore_ascii_case("bytes")) =>
+            {
+                Some(Probe {
+                    validator: validator(&response),
+                    builder,
+                    total,
+                })
+            }
+            _ => None,
+        });
+    }
+}
+
+/// Splits `total` bytes into at most `segments` ranges of nearly equal size.
+fn split(total: u64, segments: usize) -> Vec<(u64, u64)> {
+    let size = total.div_ceil(segments as u64).max(1);
+    (0..total)
+        .step_by(size as usize)
+        .map(|first| (first, (first + size).min(total) - 1))
+        .collect()
+}
+
+impl Meter {
+    fn add(&self, bytes: u64, total: Option<u64>) {
+        let transferred = self.transferred.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.callback.call(Progress {
+            transferred,
+            total,
+            elapsed: self.start.elapsed(),
+        });
+    }
+}
+
+impl State {
+    /// Fetches the part of the file, resuming it up to `max_retries` times
+    /// after a delay starting at `retry_delay`.
+    async fn run(
+        &mut self,
+        mut builder: ClientBuilder,
+        max_retries: usize,
+        retry_delay: Duration,
+    ) -> Result<(), Error> {
+        let mut retries = 0;
+        let mut delay = retry_delay;
+        let mut redirects = 0;
+        loop {
+            let mut request = builder.clone();
+            if self.offset > 0 || self.end.is_some() {
+                let end = self.end.map(|end| end.to_string()).unwrap_or_default();
+                request = request.header("Range", &format!("bytes={}-{end}", self.offset));
+                if let Some(validator) = &self.validator {
+                    request = request.header("If-Range", validator);
+                }
+            }
+            match self.fetch(request).await? {
+                Step::Done => {
+                    self.file.flush().await?;
+                    return Ok(());
+                }
+                Step::Interrupted(err) => {
+                    if retries >= max_retries {
+                        return Err(err);
+                    }
+                    retries += 1;
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2).min(MAX_RETRY_DELAY);
+                }
+                Step::Redirect(url) => {
+                    redirects += 1;
+                    if redirects >= builder.config.max_redirects {
+                        return Err(Error::MaxRedirects);
+                    }
+                    builder = builder.redirect_to(&url);
+                }
+            }
+        }
+    }
+
+    /// Sends one request and writes its body to the file. Errors of the
+    /// connection interrupt the attempt, other errors end the download.
+    async fn fetch(&mut self, request: ClientBuilder) -> Result<Step, Error> {
+        let url = request.url.clone();
+        let (response, mut body) = match request.build().await {
+            Ok(client) => match client.send_streaming().await {
+                Ok(exchange) => exchange,
+                Err(err) => return Ok(Step::Interrupted(err)),
+            },
+            Err(err) => return Ok(Step::Interrupted(err)),
+        };
+        match response.status_code().as_u16() {
+            // A segment cannot start over, the other segments hold the old file.
+            code @ (200 | 416) if self.end.is_some() => {
+                return Err(Error::DownloadStatus(code));
+            }
+            200 => {
+                // The server ignored the range or the file changed.
+                self.truncate().await?;
+                self.total = response.content_len().map(|len| len as u64);
+                self.set_validator(validator(&response)).await?;
+            }
+            206 => {
+                let value = response.header("Content-Range").unwrap_or_default();
+                match ContentRange::parse(&value) {
+                    Some(ContentRange {
+                        range: Some((first, last)),
+                        length,
+                    }) if first == self.offset
+                        && self
+                            .end
+                            .is_none_or(|end| last == end && length == self.total) =>
+                    {
+                        self.total = length.or(self.total);
+                    }
+                    _ => return Err(Error::ContentRange(value)),
+                }
+            }
+            416 => {
+                let value = response.header("Content-Range").unwrap_or_default();
+                let length = ContentRange::parse(&value).and_then(|range| range.length);
+                if self.offset > 0 && length.or(self.total) == Some(self.offset) {
+                    return Ok(Step::Done);
+                }
+                self.truncate().await?;
+                return Ok(Step::Interrupted(Error::DownloadStatus(416)));
+            }
+            code if response.status_code().is_redirect() => {
+                let location = response.header("Location");
+                return match (url, location) {
+                    (Some(url), Some(location)) => Ok(Step::Redirect(url.join(&location)?)),
+                    _ => Err(Error::DownloadStatus(code)),
+                };
+            }
+            code => return Err(Error::DownloadStatus(code)),
+        }
+        loop {
+            match body.chunk().await {
+                Ok(Some(data)) => {
+                    self.file.write_all(&data).await?;
+                    self.offset += data.len() as u64;
+                    if let Some(meter) = &self.meter {
+                        meter.add(data.len() as u64, self.total);
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => return Ok(Step::Interrupted(err)),
+            }
+        }
+        let expected = self.end.map(|end| end + 1).or(self.total);
+        if expected.is_some_and(|expected| self.offset < expected) {
+            return Ok(Step::Interrupted(Error::Io(
+                std::io::ErrorKind::UnexpectedEof.into(),
+            )));
+        }
+        Ok(Step::Done)
+    }
+
+    /// Keeps `validator` for the next attempts, and in the validator file for
+    /// later downloads.
+    async fn set_validator(&mut self, validator: Option<String>) -> Result<(), Error> {
+        if let Some(path) = &self.validator_path {
+            match &validator {
+                Some(validator) => tokio::fs::write(path, validator).await?,
+                None => {
+                    let _ = tokio::fs::remove_file(path).await;
+                }
+            }
+        }
+        self.validator = validator;
+        Ok(())
+    }
+
+    async fn truncate(&mut self) -> Result<(), Error> {
+        if self.offset > 0 {
+            self.file.set_len(0).await?;
+            self.file.rewind().await?;
+            if let Some(meter) = &self.meter {
+                meter.transferred.fetch_sub(self.offset, Ordering::Relaxed);
+            }
+            self.offset = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Strong `ETag` or `Last-Modified` of a response, usable in `If-Range`.
+fn validator(response: &Response) -> Option<String> {
+    response
+        .header("ETag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| response.header("Last-Modified"))
+}
+
+/// Value of a `Content-Range` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ContentRange {
+    /// First and last byte, absent in `bytes */length`.
+    pub(crate) range: Option<(u64, u64)>,
+    pub(crate) length: Option<u64>,
+}
+
+impl ContentRange {
+    /// Parses `bytes first-last/length`, where the length may be `*`.
+    pub(crate) fn parse(value: &str) -> Option<ContentRange> {
+        let (range, length) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+        let length = match length.trim() {
+            "*" => None,
+            length => Some(length.parse().ok()?),
+        };
+        let range = match range.trim() {
+            "*" => None,
+            range => {
+                let (first, last) = range.split_once('-')?;
+                let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+                if first > last || length.is_some_and(|length| last >= length) {
+                    return None;
+                }
+                Some((first, last))
+            }
+        };
+        Some(ContentRange { range, length })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(range: Option<(u64, u64)>, length: Option<u64>) -> Option<ContentRange> {
+        Some(ContentRange { range, length })
+    }
+
+    #[test]
+    fn content_range() {
+        let parse = ContentRange::parse;
+        assert_eq!(parse("bytes 4-9/10"), range(Some((4, 9)), Some(10)));
+        assert_eq!(parse("bytes 0-99/*"), range(Some((0, 99)), None));
+        assert_eq!(parse("bytes */10"), range(None, Some(10)));
+        assert_eq!(parse("bytes 4-10/10"), None);
+        assert_eq!(parse("bytes 9-4/10"), None);
+        assert_eq!(parse("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn split_segments() {
+        assert_eq!(split(10, 3), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(split(9, 3), vec![(0, 2), (3, 5), (6, 8)]);
+        assert_eq!(split(2, 4), vec![(0, 0), (1, 1)]);
+        assert_eq!(split(5, 1), vec![(0, 4)]);
+    }
+}
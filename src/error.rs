@@ -60,6 +60,91 @@ pub enum Error {
     HeaderWrongName,
     #[error("Maximum number of redirects reached")]
     MaxRedirects,
+    #[error("Socks4 request rejected with code {0}")]
+    Socks4Rejected(u8),
+    #[error("Socks4 wrong reply version {0}")]
+    Socks4Version(u8),
+    #[error("Socks4 target has no ipv4 address")]
+    Socks4Ipv4,
+    #[error("Proxy CONNECT failed with status {0}")]
+    ProxyConnect(u16),
+    #[error("No proxy available in pool")]
+    NoProxyAvailable,
+    #[error("Proxy hop {0} ({1}) failed: {2}")]
+    ProxyHop(usize, String, Box<Error>),
+    #[error("Server refused to switch protocols with status {0}")]
+    UpgradeRefused(u16),
+    #[error("Event stream answered with status {0}")]
+    EventSourceStatus(u16),
+    #[error("Event stream has content type {0:?}")]
+    EventSourceType(String),
+    #[error("Event stream line longer than {0} bytes")]
+    EventSourceLine(usize),
+    #[error("Download answered with status {0}")]
+    DownloadStatus(u16),
+    #[error("Invalid Content-Range {0:?}")]
+    ContentRange(String),
+    #[error("WebSocket handshake failed: {0}")]
+    WebSocketHandshake(String),
+    #[error("WebSocket protocol error: {0}")]
+    WebSocketProtocol(String),
+    #[error("WebSocket closed")]
+    WebSocketClosed,
+    #[error("No cached response for an only-if-cached request")]
+    NotCached,
+    #[error("No recorded exchange matches {0}")]
+    NotRecorded(String),
+    #[error("Invalid cassette file")]
+    InvalidCassette,
+    #[error("Real client address unknown, set or detect it before checking proxies")]
+    RealIpUnknown,
+    #[error("Conflicting Content-Length values")]
+    ConflictingContentLength,
+    #[error("Can't read netrc file {0}")]
+    Netrc(String),
+}
+
+impl Error {
+    /// Wraps an error of the `hop`-th proxy of a chain, counted from 1. The
+    /// proxy is named without its credentials.
+    pub(crate) fn proxy_hop(hop: usize, proxy: &url::Url, err: Error) -> Error {
+        let name = format!(
+            "{}://{}:{}",
+            proxy.scheme(),
+            proxy.host_str().unwrap_or_default(),
+            proxy.port_or_known_default().unwrap_or_default()
+        );
+        Error::ProxyHop(hop, name, Box::new(err))
+    }
+
+    /// Short category of the error, suitable as a metrics label: `timeout`,
+    /// `io`, `dns`, `tls`, `proxy`, `url`, `redirect`, `cache`, `cassette` or
+    /// `protocol`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Io(err) if err.kind() == std::io::ErrorKind::TimedOut => "timeout",
+            Error::Io(_) | Error::Netrc(_) => "io",
+            Error::SocketAddr => "dns",
+            Error::InvalidDnsNameError(_) => "tls",
+            Error::Socks5(_)
+            | Error::Socks4Rejected(_)
+            | Error::Socks4Version(_)
+            | Error::Socks4Ipv4
+            | Error::ProxyConnect(_)
+            | Error::NoProxyAvailable
+            | Error::ProxyHop(..)
+            | Error::UnsupportedProxyScheme(_) => "proxy",
+            Error::EmptyUrl
+            | Error::EmptyHost
+            | Error::UrlError(_)
+            | Error::UntoUri(_)
+            | Error::UnsupportedScheme(_) => "url",
+            Error::MaxRedirects => "redirect",
+            Error::NotCached => "cache",
+            Error::NotRecorded(_) | Error::InvalidCassette => "cassette",
+            _ => "protocol",
+        }
+    }
 }
 
 impl PartialEq for Error {
@@ -111,6 +196,44 @@ impl PartialEq for Error {
             (Error::HeaderWrongNameStart, Error::HeaderWrongNameStart) => true,
             (Error::HeaderWrongName, Error::HeaderWrongName) => true,
             (Error::MaxRedirects, Error::MaxRedirects) => true,
+            (Error::Socks4Rejected(code), Error::Socks4Rejected(other_code)) => code == other_code,
+            (Error::Socks4Version(version), Error::Socks4Version(other_version)) => {
+                version == other_version
+            }
+            (Error::Socks4Ipv4, Error::Socks4Ipv4) => true,
+            (Error::ProxyConnect(code), Error::ProxyConnect(other_code)) => code == other_code,
+            (Error::NoProxyAvailable, Error::NoProxyAvailable) => true,
+            (
+                Error::ProxyHop(hop, proxy, err),
+                Error::ProxyHop(other_hop, other_proxy, other_err),
+            ) => hop == other_hop && proxy == other_proxy && err == other_err,
+            (Error::UpgradeRefused(code), Error::UpgradeRefused(other_code)) => code == other_code,
+            (Error::EventSourceStatus(code), Error::EventSourceStatus(other_code)) => {
+                code == other_code
+            }
+            (Error::EventSourceType(mime), Error::EventSourceType(other_mime)) => {
+                mime == other_mime
+            }
+            (Error::EventSourceLine(length), Error::EventSourceLine(other_length)) => {
+                length == other_length
+            }
+            (Error::DownloadStatus(code), Error::DownloadStatus(other_code)) => code == other_code,
+            (Error::ContentRange(range), Error::ContentRange(other_range)) => range == other_range,
+            (Error::WebSocketHandshake(err), Error::WebSocketHandshake(other_err)) => {
+                err == other_err
+            }
+            (Error::WebSocketProtocol(err), Error::WebSocketProtocol(other_err)) => {
+                err == other_err
+            }
+            (Error::WebSocketClosed, Error::WebSocketClosed) => true,
+            (Error::NotCached, Error::NotCached) => true,
+            (Error::NotRecorded(request), Error::NotRecorded(other_request)) => {
+                request == other_request
+            }
+            (Error::InvalidCassette, Error::InvalidCassette) => true,
+            (Error::RealIpUnknown, Error::RealIpUnknown) => true,
+            (Error::ConflictingContentLength, Error::ConflictingContentLength) => true,
+            (Error::Netrc(err), Error::Netrc(other_err)) => err == other_err,
             _ => false,
         }
     }
@@ -13,16 +13,65 @@ use crate::{
     utils::{array_from_string, host_header, relative_quality_factor},
 };
 
+/// Headers carrying credentials, kept out of logs, archives and recordings.
+pub(crate) const SECRET_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+pub(crate) const REDACTED: &str = "REDACTED";
+
+/// Fields defined as comma separated lists, which can be repeated and are
+/// combined into one field when they are.
+const LIST_FIELDS: [&str; 31] = [
+    "accept",
+    "accept-charset",
+    "accept-encoding",
+    "accept-language",
+    "accept-ranges",
+    "access-control-allow-headers",
+    "access-control-allow-methods",
+    "access-control-expose-headers",
+    "allow",
+    "alt-svc",
+    "cache-control",
+    "connection",
+    "content-encoding",
+    "content-language",
+    "expect",
+    "forwarded",
+    "if-match",
+    "if-none-match",
+    "link",
+    "pragma",
+    "proxy-authenticate",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "vary",
+    "via",
+    "warning",
+    "www-authenticate",
+    "x-forwarded-for",
+    "x-forwarded-proto",
+];
+
+/// Header fields keyed by lower case name. `Set-Cookie` can't be combined
+/// into a list, so the values of repeated ones before the last are kept
+/// apart.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Headers(HashMap<String, String>);
+pub struct Headers(HashMap<String, String>, Vec<String>);
 
 impl Headers {
     pub fn new() -> Headers {
-        Headers(HashMap::new())
+        Headers(HashMap::new(), Vec::new())
     }
 
     pub fn with_capacity(capacity: usize) -> Headers {
-        Headers(HashMap::with_capacity(capacity))
+        Headers(HashMap::with_capacity(capacity), Vec::new())
     }
 
     pub fn default_http(url: &Url) -> Headers {
@@ -41,25 +90,101 @@ impl Headers {
         headers
     }
 
-    pub fn iter(&self) -> hash_map::Iter<'_, String, String> {
-        self.0.iter()
+    /// All fields, with one item per `Set-Cookie` value.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        let cookies = self
+            .0
+            .get_key_value("set-cookie")
+            .into_iter()
+            .flat_map(|(name, _)| self.1.iter().map(move |value| (name, value)));
+        cookies.chain(self.0.iter())
     }
 
+    /// Value of the field, the last one for `Set-Cookie`.
     pub fn get<T: ToString + ?Sized>(&self, key: &T) -> Option<String> {
         self.0.get(&key.to_string().to_lowercase()).cloned()
     }
 
+    /// Every value of the field, several only for repeated `Set-Cookie`.
+    pub fn get_all<T: ToString + ?Sized>(&self, key: &T) -> Vec<String> {
+        let key = key.to_string().to_lowercase();
+        match self.0.get(&key) {
+            Some(last) if key == "set-cookie" => {
+                let mut values = self.1.clone();
+                values.push(last.clone());
+                values
+            }
+            Some(value) => vec![value.clone()],
+            None => Vec::new(),
+        }
+    }
+
     pub fn insert<T: ToString + ?Sized, U: ToString + ?Sized>(
         &mut self,
         key: &T,
         value: &U,
     ) -> Option<String> {
-        self.0
-            .insert(key.to_string().to_lowercase(), value.to_string())
+        let key = key.to_string().to_lowercase();
+        if key == "set-cookie" {
+            self.1.clear();
+        }
+        self.0.insert(key, value.to_string())
     }
 
     pub fn remove<T: ToString + ?Sized>(&mut self, key: &T) -> Option<String> {
-        self.0.remove(&key.to_string().to_lowercase())
+        let key = key.to_string().to_lowercase();
+        if key == "set-cookie" {
+            self.1.clear();
+        }
+        self.0.remove(&key)
+    }
+
+    /// Replaces the values of the headers `names` that are present with
+    /// `REDACTED`.
+    pub(crate) fn redact<T: AsRef<str>>(&mut self, names: &[T]) {
+        for name in names {
+            let name = name.as_ref().to_lowercase();
+            if let Some(value) = self.0.get_mut(&name) {
+                *value = REDACTED.to_string();
+            }
+            if name == "set-cookie" {
+                self.1.fill(REDACTED.to_string());
+            }
+        }
+    }
+
+    /// Adds a field read from a message head. Repeated list fields are
+    /// combined into one comma separated list, `Set-Cookie` values are all
+    /// kept, and other repeated fields replace the earlier ones. Repeated
+    /// `Content-Length` values must agree and are collapsed into one.
+    pub(crate) fn append(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let key = key.to_lowercase();
+        let value = if key == "content-length" {
+            single_content_length(value)?
+        } else {
+            value
+        };
+        match self.0.entry(key) {
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(value.to_string());
+            }
+            hash_map::Entry::Occupied(mut entry) => match entry.key().as_str() {
+                "set-cookie" => self.1.push(entry.insert(value.to_string())),
+                "content-length" if entry.get() != value => {
+                    return Err(Error::ConflictingContentLength);
+                }
+                "content-length" => {}
+                name if LIST_FIELDS.contains(&name) => {
+                    let combined = entry.get_mut();
+                    combined.push_str(", ");
+                    combined.push_str(value);
+                }
+                _ => {
+                    entry.insert(value.to_string());
+                }
+            },
+        }
+        Ok(())
     }
 
     pub fn get_q<T: ToString + ?Sized>(&self, key: &T) -> Option<f32> {
@@ -77,6 +202,18 @@ impl Headers {
     }
 }
 
+/// The value of a `Content-Length` field, which may be a list of the same
+/// value repeated.
+fn single_content_length(value: &str) -> Result<&str, Error> {
+    let mut values = value.split(',').map(str::trim);
+    let first = values.next().unwrap_or_default();
+    if values.all(|other| other == first) {
+        Ok(first)
+    } else {
+        Err(Error::ConflictingContentLength)
+    }
+}
+
 impl Default for Headers {
     fn default() -> Self {
         Self::new()
@@ -90,19 +227,14 @@ impl FromStr for Headers {
         let headers = s.trim();
 
         if headers.lines().all(|e| e.contains(':')) {
-            let headers = headers
-                .lines()
-                .map(|elem| {
-                    let idx = elem.find(':').unwrap();
-                    let (key, value) = elem.split_at(idx);
-                    (
-                        key.to_string().to_lowercase(),
-                        value[1..].trim().to_string(),
-                    )
-                })
-                .collect();
-
-            Ok(Headers(headers))
+            let mut map = Headers::new();
+            for elem in headers.lines() {
+                let idx = elem.find(':').unwrap();
+                let (key, value) = elem.split_at(idx);
+                map.append(key, value[1..].trim())?;
+            }
+
+            Ok(map)
         } else {
             Err(Error::ParseHeaders)
         }
@@ -115,7 +247,7 @@ impl From<HashMap<String, String>> for Headers {
             .iter()
             .map(|(key, value)| (key.to_string().to_lowercase(), value.to_string()))
             .collect();
-        Headers(headers)
+        Headers(headers, Vec::new())
     }
 }
 
@@ -148,7 +280,7 @@ mod tests {
 
     #[test]
     fn headers_new() {
-        assert_eq!(Headers::new(), Headers(HashMap::new()));
+        assert_eq!(Headers::new(), Headers(HashMap::new(), Vec::new()));
     }
 
     #[test]
@@ -166,7 +298,7 @@ mod tests {
     fn headers_insert() {
         let mut headers_expect = HashMap::new();
         headers_expect.insert("connection".to_string(), "Close".to_string());
-        let headers_expect = Headers(headers_expect);
+        let headers_expect = Headers(headers_expect, Vec::new());
         let mut headers = Headers::new();
         headers.insert("Connection", "Close");
 
@@ -183,6 +315,58 @@ mod tests {
         assert_eq!(Headers::default_http(&url.into_url().unwrap()), headers);
     }
 
+    #[test]
+    fn headers_from_str_repeated() {
+        let headers = "WWW-Authenticate: Basic realm=\"a\"\r\n\
+                       WWW-Authenticate: Bearer\r\n\
+                       Set-Cookie: a=1\r\n\
+                       Set-Cookie: b=2\r\n"
+            .parse::<Headers>()
+            .unwrap();
+
+        assert_eq!(
+            headers.get("WWW-Authenticate"),
+            Some("Basic realm=\"a\", Bearer".to_string())
+        );
+        assert_eq!(headers.get("Set-Cookie"), Some("b=2".to_string()));
+        assert_eq!(headers.get_all("Set-Cookie"), vec!["a=1", "b=2"]);
+        assert_eq!(headers.iter().count(), 3);
+    }
+
+    #[test]
+    fn headers_from_str_singletons() {
+        let headers = "Content-Type: text/plain\r\n\
+                       Content-Type: text/html\r\n\
+                       Content-Length: 5\r\n\
+                       Content-Length: 5, 5\r\n"
+            .parse::<Headers>()
+            .unwrap();
+
+        assert_eq!(headers.get("Content-Type"), Some("text/html".to_string()));
+        assert_eq!(headers.content_length(), Some(5));
+        assert_eq!(
+            "Content-Length: 5\r\nContent-Length: 6\r\n".parse::<Headers>(),
+            Err(Error::ConflictingContentLength)
+        );
+        assert_eq!(
+            "Content-Length: 5, 6\r\n".parse::<Headers>(),
+            Err(Error::ConflictingContentLength)
+        );
+    }
+
+    #[test]
+    fn headers_set_cookie() {
+        let mut headers = "Set-Cookie: a=1\r\nSet-Cookie: b=2\r\n"
+            .parse::<Headers>()
+            .unwrap();
+        headers.redact(&SECRET_HEADERS);
+        assert_eq!(headers.get_all("set-cookie"), vec![REDACTED, REDACTED]);
+        headers.insert("Set-Cookie", "c=3");
+        assert_eq!(headers.get_all("set-cookie"), vec!["c=3"]);
+        headers.remove("Set-Cookie");
+        assert!(headers.get_all("set-cookie").is_empty());
+    }
+
     #[test]
     fn headers_from_str() {
         let mut headers_expect = HashMap::with_capacity(2);
@@ -208,7 +392,7 @@ mod tests {
         headers_expect.insert("content-length".to_string(), "100".to_string());
 
         assert_eq!(
-            Headers(headers_expect.clone()),
+            Headers(headers_expect.clone(), Vec::new()),
             Headers::from(headers_expect)
         );
     }
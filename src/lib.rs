@@ -4,31 +4,78 @@ The `netc` crate is HTTP client for the [Rust](http://rust-lang.org/) programmin
 
 */
 
+pub mod batch;
+mod bearer;
+pub mod body;
+pub mod buf_stream;
+pub mod cache;
+pub mod cassette;
+pub mod challenge;
+pub mod checker;
 pub mod client;
 pub mod client_builder;
+mod digest;
+pub mod download;
 pub mod error;
+pub mod har;
 pub mod header;
 pub mod headers;
+pub mod limiter;
 pub mod method;
+pub mod netrc;
+pub mod observer;
+pub mod progress;
+pub mod proxy;
+pub mod proxy_pool;
 pub mod request;
 pub mod response;
+mod socks4;
+pub mod sse;
 pub mod status;
 pub mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod timings;
+#[cfg(feature = "tracing")]
+mod trace;
 mod utils;
 pub mod version;
+pub mod websocket;
 
 use utils::IntoUrl;
 
+pub use crate::batch::{Batch, Fetches};
+pub use crate::bearer::TokenProvider;
+pub use crate::body::Body;
+pub use crate::buf_stream::BufStream;
+pub use crate::cache::{Cache, CacheMode, CacheStorage, DiskStorage, MemoryStorage};
+pub use crate::cassette::{Cassette, CassetteMode, Matcher};
+pub use crate::challenge::Challenge;
+pub use crate::checker::Checker;
 pub use crate::client::Client;
 pub use crate::client_builder::ClientBuilder;
+pub use crate::digest::AuthCache;
+pub use crate::download::Download;
 pub use crate::error::Error;
+pub use crate::har::{HarEntry, HarRecorder};
 pub use crate::headers::Headers;
+pub use crate::limiter::{BandwidthLimiter, RateLimiter, Throttled};
 pub use crate::method::Method;
+pub use crate::netrc::Netrc;
+#[cfg(feature = "metrics")]
+pub use crate::observer::MetricsFacade;
+pub use crate::observer::{MetricsObserver, RequestEvent};
+pub use crate::progress::Progress;
+pub use crate::proxy::EnvProxy;
+pub use crate::proxy_pool::{ProxyPool, ProxyStats, Strategy};
 pub use crate::request::Request;
 pub use crate::response::Response;
+pub use crate::sse::{Event, EventSource};
 pub use crate::status::{Status, StatusCode};
 pub use crate::stream::HttpStream;
+pub use crate::timings::Timings;
 pub use crate::version::Version;
+pub use crate::websocket::{CloseFrame, Message, WebSocket};
 
 #[cfg(test)]
 pub(crate) fn my_ip() -> String {
@@ -66,7 +113,7 @@ mod tests {
     static IP: OnceLock<String> = OnceLock::new();
 
     pub fn ip_str() -> &'static str {
-        IP.get_or_init(|| crate::my_ip())
+        IP.get_or_init(crate::my_ip)
     }
 }
 
@@ -0,0 +1,373 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::FutureExt;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+/// Slowest request rate, one request a day.
+const MIN_RATE: f64 = 1.0 / 86_400.0;
+
+/// Token bucket refilled at `rate` tokens per second up to `burst` tokens.
+///
+/// Takes may overdraw the bucket; the debt is the time callers wait, so
+/// concurrent callers are served in the order they asked.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    rate: f64,
+    burst: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, burst: f64, now: Instant) -> Bucket {
+        Bucket {
+            tokens: burst,
+            rate,
+            burst,
+            updated: now,
+        }
+    }
+
+    /// Takes `amount` tokens and returns how long to wait until they are
+    /// covered by the refill.
+    fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - amount;
+        self.updated = now;
+        match self.tokens {
+            tokens if tokens >= 0.0 => Duration::ZERO,
+            tokens => Duration::try_from_secs_f64(-tokens / self.rate).unwrap_or(Duration::MAX),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Hosts {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<String, Bucket>,
+}
+
+/// Limits the number of requests per second sent to each host.
+///
+/// Every host gets its own token bucket. Cloning the limiter is cheap and all
+/// clones share the buckets, so clients using the same limiter stay under the
+/// limit together.
+#[derive(Clone)]
+pub struct RateLimiter(Arc<Mutex<Hosts>>);
+
+impl RateLimiter {
+    /// Allows `per_second` requests per second to a host, without bursts.
+    /// Rates below one request a day, NaN included, are raised to it.
+    pub fn new(per_second: f64) -> RateLimiter {
+        let rate = match per_second.is_nan() {
+            true => MIN_RATE,
+            false => per_second.clamp(MIN_RATE, f64::MAX),
+        };
+        RateLimiter(Arc::new(Mutex::new(Hosts {
+            rate,
+            burst: 1.0,
+            buckets: HashMap::new(),
+        })))
+    }
+
+    /// Lets up to `burst` requests to an idle host go out at once.
+    pub fn burst(self, burst: u32) -> RateLimiter {
+        self.lock().burst = burst.max(1) as f64;
+        self
+    }
+
+    /// Waits until a request to `host` may be sent.
+    pub async fn acquire(&self, host: &str) {
+        let wait = self.reserve(host, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn reserve(&self, host: &str, now: Instant) -> Duration {
+        let mut hosts = self.lock();
+        let (rate, burst) = (hosts.rate, hosts.burst);
+        hosts
+            .buckets
+            .entry(host.to_ascii_lowercase())
+            .or_insert_with(|| Bucket::new(rate, burst, now))
+            .take(1.0, now)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Hosts> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hosts = self.lock();
+        f.debug_struct("RateLimiter")
+            .field("rate", &hosts.rate)
+            .field("burst", &hosts.burst)
+            .finish()
+    }
+}
+
+impl PartialEq for RateLimiter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for RateLimiter {}
+
+/// Limits the bytes per second passing through the streams it wraps.
+///
+/// All streams wrapped by one limiter and its clones share a single budget of
+/// up to one second worth of bytes.
+#[derive(Clone)]
+pub struct BandwidthLimiter(Arc<Mutex<Bucket>>);
+
+impl BandwidthLimiter {
+    pub fn new(bytes_per_second: u64) -> BandwidthLimiter {
+        let rate = bytes_per_second.max(1) as f64;
+        BandwidthLimiter(Arc::new(Mutex::new(Bucket::new(
+            rate,
+            rate,
+            Instant::now(),
+        ))))
+    }
+
+    /// Limits reads and writes of `inner` with this limiter.
+    pub fn wrap<S>(&self, inner: S) -> Throttled<S> {
+        Throttled::new(inner, Some(self.clone()), Some(self.clone()))
+    }
+
+    fn spend(&self, bytes: usize) {
+        self.lock().take(bytes as f64, Instant::now());
+    }
+
+    /// Time until the budget is no longer overdrawn.
+    fn wait(&self) -> Duration {
+        self.lock().take(0.0, Instant::now())
+    }
+
+    /// Largest write that does not exceed the budget of one second.
+    fn max_write(&self) -> usize {
+        self.lock().burst as usize
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl fmt::Debug for BandwidthLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("BandwidthLimiter")
+            .field(&self.lock().rate)
+            .finish()
+    }
+}
+
+impl PartialEq for BandwidthLimiter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for BandwidthLimiter {}
+
+/// Stream whose reads and writes are paced by [`BandwidthLimiter`]s.
+///
+/// Bytes are passed on as soon as they arrive and paid for afterwards: while
+/// the shared budget is overdrawn, reads and writes of every stream using the
+/// limiter wait until it is refilled.
+pub struct Throttled<S> {
+    inner: S,
+    read: Option<BandwidthLimiter>,
+    write: Option<BandwidthLimiter>,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(
+        inner: S,
+        read: Option<BandwidthLimiter>,
+        write: Option<BandwidthLimiter>,
+    ) -> Throttled<S> {
+        Throttled {
+            inner,
+            read,
+            write,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// Waits until `limiter` has budget left, keeping the timer in `delay`.
+fn poll_budget(
+    limiter: Option<&BandwidthLimiter>,
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    let Some(limiter) = limiter else {
+        return Poll::Ready(());
+    };
+    loop {
+        if let Some(sleep) = delay {
+            futures::ready!(sleep.poll_unpin(cx));
+            *delay = None;
+        }
+        match limiter.wait() {
+            wait if wait.is_zero() => return Poll::Ready(()),
+            wait => *delay = Some(Box::pin(tokio::time::sleep(wait))),
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Throttled<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Throttled")
+            .field("inner", &self.inner)
+            .field("read", &self.read)
+            .field("write", &self.write)
+            .finish()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        let this = Pin::get_mut(self);
+        futures::ready!(poll_budget(this.read.as_ref(), &mut this.read_delay, cx));
+        let before = buf.filled().len();
+        futures::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(limiter) = &this.read {
+            limiter.spend(buf.filled().len() - before);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::get_mut(self);
+        futures::ready!(poll_budget(this.write.as_ref(), &mut this.write_delay, cx));
+        let Some(limiter) = &this.write else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        let len = buf.len().min(limiter.max_write().max(1));
+        let written = futures::ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]))?;
+        limiter.spend(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut Pin::get_mut(self).inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut Pin::get_mut(self).inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+    use super::*;
+
+    #[test]
+    fn bucket_refill() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(10.0, 2.0, now);
+        assert_eq!(bucket.take(1.0, now), Duration::ZERO);
+        assert_eq!(bucket.take(1.0, now), Duration::ZERO);
+        assert_eq!(bucket.take(1.0, now), Duration::from_millis(100));
+        assert_eq!(bucket.take(1.0, now), Duration::from_millis(200));
+        let later = now + Duration::from_secs(10);
+        assert_eq!(bucket.take(2.0, later), Duration::ZERO);
+    }
+
+    #[test]
+    fn rate_per_host() {
+        let limiter = RateLimiter::new(2.0).burst(2);
+        let shared = limiter.clone();
+        let now = Instant::now();
+        assert_eq!(limiter.reserve("a.com", now), Duration::ZERO);
+        assert_eq!(shared.reserve("A.com", now), Duration::ZERO);
+        assert_eq!(limiter.reserve("a.com", now), Duration::from_millis(500));
+        assert_eq!(limiter.reserve("b.com", now), Duration::ZERO);
+        assert_eq!(limiter, shared);
+        assert_ne!(limiter, RateLimiter::new(2.0));
+    }
+
+    #[test]
+    fn invalid_rates() {
+        let now = Instant::now();
+        let day = Duration::from_secs(86_400);
+        for rate in [0.0, -1.0, f64::NAN, f64::MIN_POSITIVE] {
+            let limiter = RateLimiter::new(rate);
+            assert_eq!(limiter.reserve("a.com", now), Duration::ZERO);
+            let wait = limiter.reserve("a.com", now);
+            assert!(wait.abs_diff(day) < Duration::from_millis(1));
+        }
+        let limiter = RateLimiter::new(f64::INFINITY);
+        assert_eq!(limiter.reserve("a.com", now), Duration::ZERO);
+        assert_eq!(limiter.reserve("a.com", now), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn throttled_read() {
+        let (mut client, server) = duplex(1 << 16);
+        let mut server = BandwidthLimiter::new(10_000).wrap(server);
+        client.write_all(&[7u8; 15_000]).await.unwrap();
+        let start = Instant::now();
+        let mut piece = [0u8; 1000];
+        for _ in 0..15 {
+            server.read_exact(&mut piece).await.unwrap();
+        }
+        // The burst covers 10 pieces, the 11th overdraws the budget and every
+        // later piece waits 100ms for it to be refilled.
+        assert!(start.elapsed() >= Duration::from_millis(350));
+    }
+
+    #[tokio::test]
+    async fn throttled_write_pieces() {
+        let (client, mut server) = duplex(1 << 16);
+        let mut client = BandwidthLimiter::new(100).wrap(client);
+        assert_eq!(client.write(&[1u8; 1000]).await.unwrap(), 100);
+        let mut piece = [0u8; 100];
+        server.read_exact(&mut piece).await.unwrap();
+        assert_eq!(piece, [1u8; 100]);
+    }
+}
@@ -0,0 +1,208 @@
+// https://www.gnu.org/software/inetutils/manual/html_node/The-_002enetrc-file.html
+use std::{fs, path::Path};
+
+use url::Url;
+
+use crate::{Error, digest::Credentials};
+
+/// Credentials of a `.netrc` file. Machine names are matched against the host
+/// of a URL, the `default` entry is used for every other host.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Netrc {
+    machines: Vec<(String, Credentials)>,
+    default: Option<Credentials>,
+}
+
+impl Netrc {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Netrc, Error> {
+        Ok(Netrc::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parses the contents of a `.netrc` file. Macro definitions and `account`
+    /// values are skipped, as are tokens outside of an entry.
+    pub fn parse(input: &str) -> Netrc {
+        let mut netrc = Netrc::default();
+        let mut tokens = Tokens { input, pos: 0 };
+        // The entry being read: its machine name, `None` for `default`.
+        let mut entry: Option<(Option<String>, Credentials)> = None;
+        while let Some(token) = tokens.next() {
+            match token.as_str() {
+                "machine" | "default" => {
+                    netrc.push(entry.take());
+                    let machine = match token.as_str() {
+                        "machine" => match tokens.next() {
+                            Some(machine) => Some(machine),
+                            None => break,
+                        },
+                        _ => None,
+                    };
+                    entry = Some((machine, Credentials::new("", "")));
+                }
+                "login" | "password" | "account" => {
+                    let value = tokens.next().unwrap_or_default();
+                    if let Some((_, credentials)) = &mut entry {
+                        match token.as_str() {
+                            "login" => credentials.username = value,
+                            "password" => credentials.password = value,
+                            _ => {}
+                        }
+                    }
+                }
+                "macdef" => {
+                    tokens.next();
+                    tokens.skip_macro();
+                }
+                _ => {}
+            }
+        }
+        netrc.push(entry);
+        netrc
+    }
+
+    /// Credentials for the host of `url`. The first matching `machine` entry
+    /// wins, like in `ftp` and `curl`.
+    pub(crate) fn credentials(&self, url: &Url) -> Option<&Credentials> {
+        self.machine_credentials(url).or(self.default.as_ref())
+    }
+
+    /// Credentials of the `machine` entry for the host of `url`, ignoring the
+    /// `default` entry.
+    pub(crate) fn machine_credentials(&self, url: &Url) -> Option<&Credentials> {
+        let host = url.host_str()?;
+        self.machines
+            .iter()
+            .find(|(machine, _)| machine.eq_ignore_ascii_case(host))
+            .map(|(_, credentials)| credentials)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty() && self.default.is_none()
+    }
+
+    fn push(&mut self, entry: Option<(Option<String>, Credentials)>) {
+        match entry {
+            Some((Some(machine), credentials)) => self.machines.push((machine, credentials)),
+            Some((None, credentials)) => self.default = Some(credentials),
+            None => {}
+        }
+    }
+}
+
+struct Tokens<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    /// Next whitespace separated token. Double quoted tokens may contain
+    /// whitespace and backslash escapes.
+    fn next(&mut self) -> Option<String> {
+        let rest = self.rest();
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        if trimmed.is_empty() {
+            return None;
+        }
+        if let Some(quoted) = trimmed.strip_prefix('"') {
+            let mut token = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((idx, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            token.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = idx + 1;
+                        break;
+                    }
+                    c => token.push(c),
+                }
+            }
+            self.pos += end + 1;
+            return Some(token);
+        }
+        let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        self.pos += end;
+        Some(trimmed[..end].to_string())
+    }
+
+    /// Skips the body of a `macdef`, which runs until the next empty line.
+    fn skip_macro(&mut self) {
+        let rest = self.rest();
+        let Some(line_end) = rest.find('\n') else {
+            self.pos = self.input.len();
+            return;
+        };
+        let body = &rest[line_end + 1..];
+        let mut end = body.len();
+        let mut offset = 0;
+        for line in body.split_inclusive('\n') {
+            offset += line.len();
+            if line.trim_end_matches(['\r', '\n']).is_empty() {
+                end = offset;
+                break;
+            }
+        }
+        self.pos += line_end + 1 + end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETRC: &str = "machine example.com login alice password \"s3cret word\"\n\
+                         \n\
+                         macdef init\n\
+                         cd /pub\n\
+                         machine evil.com login mallory password x\n\
+                         \n\
+                         machine api.example.com\n\
+                         \tlogin bob\n\
+                         \taccount ignored\n\
+                         \tpassword hunter2\n\
+                         default login anonymous password guest\n";
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn parse_machines() {
+        let netrc = Netrc::parse(NETRC);
+        let alice = netrc.credentials(&url("https://EXAMPLE.com/")).unwrap();
+        assert_eq!(alice.username, "alice");
+        assert_eq!(alice.password, "s3cret word");
+        let bob = netrc
+            .credentials(&url("http://api.example.com:8080/"))
+            .unwrap();
+        assert_eq!(bob.username, "bob");
+        assert_eq!(bob.password, "hunter2");
+    }
+
+    #[test]
+    fn skip_macdef() {
+        let netrc = Netrc::parse(NETRC);
+        let evil = netrc.credentials(&url("http://evil.com/")).unwrap();
+        assert_eq!(evil.username, "anonymous");
+        assert_eq!(netrc.machines.len(), 2);
+    }
+
+    #[test]
+    fn default_entry() {
+        let netrc = Netrc::parse("machine a.com login a password a");
+        assert!(netrc.credentials(&url("http://b.com/")).is_none());
+        let netrc = Netrc::parse(NETRC);
+        let default = netrc.credentials(&url("http://b.com/")).unwrap();
+        assert_eq!(default.password, "guest");
+        assert!(netrc.machine_credentials(&url("http://b.com/")).is_none());
+        assert!(Netrc::parse("").is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use url::Url;
+
+use crate::Method;
+
+/// Receives an event for every request a client sends.
+///
+/// `on_request` is called once per exchange of [`Client::send`], so each
+/// redirect hop and authentication retry is reported on its own, and once
+/// per [`Client::send_streaming`], when the response head arrives. Responses
+/// served from the cache or a cassette are reported too, and so are
+/// connections that failed while the client was built.
+///
+/// [`Client::send`]: crate::Client::send
+/// [`Client::send_streaming`]: crate::Client::send_streaming
+pub trait MetricsObserver: Send + Sync {
+    fn on_request(&self, event: &RequestEvent);
+}
+
+/// Outcome of one request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestEvent {
+    pub host: String,
+    pub method: Method,
+    /// Status code, `None` when the request failed.
+    pub status: Option<u16>,
+    /// [`Error::kind`](crate::Error::kind) of the error the request failed
+    /// with.
+    pub error: Option<&'static str>,
+    /// Length of the request body.
+    pub bytes_sent: usize,
+    /// Length of the response body, zero for streamed responses whose body
+    /// is read later.
+    pub bytes_received: usize,
+    pub duration: Duration,
+    /// Proxy the request went through, without its credentials. For a proxy
+    /// chain it is the first hop.
+    pub proxy: Option<Url>,
+}
+
+impl RequestEvent {
+    /// Class of the status code like `2xx`, or `error` when the request
+    /// failed.
+    pub fn status_class(&self) -> &'static str {
+        match self.status {
+            Some(100..=199) => "1xx",
+            Some(200..=299) => "2xx",
+            Some(300..=399) => "3xx",
+            Some(400..=499) => "4xx",
+            Some(500..=599) => "5xx",
+            Some(_) => "other",
+            None => "error",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Observer(pub(crate) Arc<dyn MetricsObserver>);
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Observer").finish()
+    }
+}
+
+impl PartialEq for Observer {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Observer {}
+
+/// [`MetricsObserver`] that reports to the recorder installed for the
+/// [`metrics`] facade:
+///
+/// - `netc_requests_total` counter
+/// - `netc_request_duration_seconds` histogram
+/// - `netc_sent_bytes_total` and `netc_received_bytes_total` counters
+///
+/// All are labeled with `host`, `method`, `status` (the status class),
+/// `error` (empty on success) and `proxy` (the proxy host, empty when
+/// direct).
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MetricsFacade;
+
+#[cfg(feature = "metrics")]
+impl MetricsObserver for MetricsFacade {
+    fn on_request(&self, event: &RequestEvent) {
+        let labels = labels(event);
+        metrics::counter!("netc_requests_total", &labels).increment(1);
+        metrics::histogram!("netc_request_duration_seconds", &labels).record(event.duration);
+        metrics::counter!("netc_sent_bytes_total", &labels).increment(event.bytes_sent as u64);
+        metrics::counter!("netc_received_bytes_total", &labels)
+            .increment(event.bytes_received as u64);
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn labels(event: &RequestEvent) -> [(&'static str, String); 5] {
+    [
+        ("host", event.host.clone()),
+        ("method", event.method.to_string()),
+        ("status", event.status_class().to_string()),
+        ("error", event.error.unwrap_or_default().to_string()),
+        (
+            "proxy",
+            event
+                .proxy
+                .as_ref()
+                .and_then(Url::host_str)
+                .unwrap_or_default()
+                .to_string(),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(status: Option<u16>) -> RequestEvent {
+        RequestEvent {
+            host: "example.com".to_string(),
+            method: Method::Get,
+            status,
+            error: status.is_none().then_some("io"),
+            bytes_sent: 0,
+            bytes_received: 5,
+            duration: Duration::from_millis(20),
+            proxy: None,
+        }
+    }
+
+    #[test]
+    fn status_classes() {
+        assert_eq!(event(Some(101)).status_class(), "1xx");
+        assert_eq!(event(Some(204)).status_class(), "2xx");
+        assert_eq!(event(Some(304)).status_class(), "3xx");
+        assert_eq!(event(Some(404)).status_class(), "4xx");
+        assert_eq!(event(Some(503)).status_class(), "5xx");
+        assert_eq!(event(Some(999)).status_class(), "other");
+        assert_eq!(event(None).status_class(), "error");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn facade_labels() {
+        let mut failed = event(None);
+        failed.proxy = Some(Url::parse("socks5://proxy:1080").unwrap());
+        assert_eq!(
+            labels(&failed),
+            [
+                ("host", "example.com".to_string()),
+                ("method", "GET".to_string()),
+                ("status", "error".to_string()),
+                ("error", "io".to_string()),
+                ("proxy", "proxy".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+/// Bytes transferred so far by a request or download.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub transferred: u64,
+    /// Expected number of bytes, when known from the response headers.
+    pub total: Option<u64>,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Share of `total` transferred, between 0 and 1.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            Some(total) => Some(self.transferred as f64 / total as f64),
+            None => None,
+        }
+    }
+
+    /// Average rate in bytes per second.
+    pub fn rate(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.transferred as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Shared progress callback.
+#[derive(Clone)]
+pub(crate) struct ProgressFn(pub(crate) Arc<dyn Fn(Progress) + Send + Sync>);
+
+impl ProgressFn {
+    pub(crate) fn new<F>(callback: F) -> ProgressFn
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        ProgressFn(Arc::new(callback))
+    }
+
+    pub(crate) fn call(&self, progress: Progress) {
+        (self.0)(progress)
+    }
+}
+
+impl fmt::Debug for ProgressFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ProgressFn").finish()
+    }
+}
+
+impl PartialEq for ProgressFn {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProgressFn {}
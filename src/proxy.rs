@@ -0,0 +1,259 @@
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use url::{Host, Url};
+
+/// Proxy settings taken from the `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and
+/// `NO_PROXY` environment variables. Lower case names take precedence over
+/// upper case ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnvProxy {
+    http: Option<Url>,
+    https: Option<Url>,
+    all: Option<Url>,
+    no_proxy: Vec<NoProxyEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum NoProxyEntry {
+    Wildcard,
+    Domain(String, Option<u16>),
+    Ip(IpAddr, Option<u16>),
+    Cidr(IpAddr, u8),
+}
+
+impl EnvProxy {
+    pub fn from_env() -> EnvProxy {
+        EnvProxy::from_lookup(|name| env::var(name).ok())
+    }
+
+    /// Builds the settings from an arbitrary variable lookup instead of the
+    /// process environment.
+    pub fn from_lookup<F>(lookup: F) -> EnvProxy
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let var = |name: &str| {
+            lookup(&name.to_lowercase())
+                .or_else(|| lookup(name))
+                .filter(|value| !value.trim().is_empty())
+        };
+        EnvProxy {
+            http: var("HTTP_PROXY").and_then(|value| parse_proxy(&value)),
+            https: var("HTTPS_PROXY").and_then(|value| parse_proxy(&value)),
+            all: var("ALL_PROXY").and_then(|value| parse_proxy(&value)),
+            no_proxy: var("NO_PROXY")
+                .map(|value| parse_no_proxy(&value))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.http.is_none() && self.https.is_none() && self.all.is_none()
+    }
+
+    /// Returns the proxy to use for `url`, or `None` when the target should be
+    /// reached directly.
+    pub fn proxy_for(&self, url: &Url) -> Option<Url> {
+        if self.is_excluded(url) {
+            return None;
+        }
+        let proxy = match url.scheme() {
+            "http" | "ws" => self.http.as_ref(),
+            "https" | "wss" => self.https.as_ref(),
+            _ => None,
+        };
+        proxy.or(self.all.as_ref()).cloned()
+    }
+
+    pub fn is_excluded(&self, url: &Url) -> bool {
+        let Some(host) = url.host() else {
+            return false;
+        };
+        let port = url.port_or_known_default();
+        let ip = match &host {
+            Host::Ipv4(ip) => Some(IpAddr::V4(*ip)),
+            Host::Ipv6(ip) => Some(IpAddr::V6(*ip)),
+            Host::Domain(_) => None,
+        };
+        self.no_proxy.iter().any(|entry| match entry {
+            NoProxyEntry::Wildcard => true,
+            NoProxyEntry::Domain(domain, entry_port) => match &host {
+                Host::Domain(name) => {
+                    let name = name.trim_end_matches('.').to_lowercase();
+                    port_matches(*entry_port, port)
+                        && (name == *domain || name.ends_with(&format!(".{domain}")))
+                }
+                _ => false,
+            },
+            NoProxyEntry::Ip(entry_ip, entry_port) => {
+                ip == Some(*entry_ip) && port_matches(*entry_port, port)
+            }
+            NoProxyEntry::Cidr(network, prefix) => {
+                ip.is_some_and(|ip| cidr_contains(*network, *prefix, ip))
+            }
+        })
+    }
+}
+
+fn port_matches(entry: Option<u16>, port: Option<u16>) -> bool {
+    entry.is_none() || entry == port
+}
+
+fn parse_proxy(value: &str) -> Option<Url> {
+    let value = value.trim();
+    if value.contains("://") {
+        Url::parse(value).ok()
+    } else {
+        Url::parse(&format!("http://{value}")).ok()
+    }
+}
+
+fn parse_no_proxy(value: &str) -> Vec<NoProxyEntry> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(parse_no_proxy_entry)
+        .collect()
+}
+
+fn parse_no_proxy_entry(entry: &str) -> Option<NoProxyEntry> {
+    if entry == "*" {
+        return Some(NoProxyEntry::Wildcard);
+    }
+    if let Some((network, prefix)) = entry.split_once('/') {
+        let network: IpAddr = network.parse().ok()?;
+        let prefix: u8 = prefix.parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        return (prefix <= max).then_some(NoProxyEntry::Cidr(network, prefix));
+    }
+    if let Ok(ip) = entry.trim_matches(['[', ']']).parse::<IpAddr>() {
+        return Some(NoProxyEntry::Ip(ip, None));
+    }
+    let (host, port) = match entry.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port.parse::<u16>().ok()?)),
+        None => (entry, None),
+    };
+    if let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        return Some(NoProxyEntry::Ip(ip, port));
+    }
+    let domain = host
+        .trim_start_matches('*')
+        .trim_start_matches('.')
+        .trim_end_matches('.')
+        .to_lowercase();
+    if domain.is_empty() {
+        return None;
+    }
+    Some(NoProxyEntry::Domain(domain, port))
+}
+
+fn cidr_contains(network: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        (IpAddr::V4(network), IpAddr::V6(ip)) => ip
+            .to_ipv4_mapped()
+            .is_some_and(|ip| cidr_contains(IpAddr::V4(network), prefix, IpAddr::V4(ip))),
+        (IpAddr::V6(_), IpAddr::V4(ip)) => {
+            cidr_contains(network, prefix, IpAddr::V6(Ipv4Addr::to_ipv6_mapped(&ip)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn env_proxy(vars: &[(&str, &str)]) -> EnvProxy {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        EnvProxy::from_lookup(|name| vars.get(name).cloned())
+    }
+
+    fn proxy_for(env: &EnvProxy, url: &str) -> Option<String> {
+        env.proxy_for(&url.parse().unwrap())
+            .map(|proxy| proxy.to_string())
+    }
+
+    #[test]
+    fn scheme_selection() {
+        let env = env_proxy(&[
+            ("HTTP_PROXY", "http://127.0.0.1:5858"),
+            ("https_proxy", "127.0.0.1:5656"),
+            ("ALL_PROXY", "socks5h://127.0.0.1:5959"),
+        ]);
+        assert_eq!(
+            proxy_for(&env, "http://example.com"),
+            Some("http://127.0.0.1:5858/".to_string())
+        );
+        assert_eq!(
+            proxy_for(&env, "https://example.com"),
+            Some("http://127.0.0.1:5656/".to_string())
+        );
+        let env = env_proxy(&[("ALL_PROXY", "socks5h://127.0.0.1:5959")]);
+        assert_eq!(
+            proxy_for(&env, "https://example.com"),
+            Some("socks5h://127.0.0.1:5959".to_string())
+        );
+    }
+
+    #[test]
+    fn lower_case_precedence() {
+        let env = env_proxy(&[
+            ("HTTP_PROXY", "http://upper:1"),
+            ("http_proxy", "http://lower:2"),
+        ]);
+        assert_eq!(
+            proxy_for(&env, "http://example.com"),
+            Some("http://lower:2/".to_string())
+        );
+    }
+
+    #[test]
+    fn no_proxy_domains() {
+        let env = env_proxy(&[
+            ("ALL_PROXY", "http://127.0.0.1:5858"),
+            ("NO_PROXY", "localhost, .internal.net,example.com:8080"),
+        ]);
+        assert_eq!(proxy_for(&env, "http://localhost/"), None);
+        assert_eq!(proxy_for(&env, "http://a.internal.net/"), None);
+        assert_eq!(proxy_for(&env, "http://internal.net/"), None);
+        assert_eq!(proxy_for(&env, "http://example.com:8080/"), None);
+        assert!(proxy_for(&env, "http://example.com/").is_some());
+        assert!(proxy_for(&env, "http://notinternal.net/").is_some());
+    }
+
+    #[test]
+    fn no_proxy_ips() {
+        let env = env_proxy(&[
+            ("ALL_PROXY", "http://127.0.0.1:5858"),
+            ("no_proxy", "10.0.0.0/8,192.168.1.1,::1,fd00::/8"),
+        ]);
+        assert_eq!(proxy_for(&env, "http://10.20.30.40/"), None);
+        assert_eq!(proxy_for(&env, "http://192.168.1.1:8080/"), None);
+        assert_eq!(proxy_for(&env, "http://[::1]/"), None);
+        assert_eq!(proxy_for(&env, "http://[fd12::1]/"), None);
+        assert!(proxy_for(&env, "http://11.0.0.1/").is_some());
+        assert!(proxy_for(&env, "http://192.168.1.2/").is_some());
+    }
+
+    #[test]
+    fn no_proxy_wildcard() {
+        let env = env_proxy(&[("ALL_PROXY", "http://127.0.0.1:5858"), ("NO_PROXY", "*")]);
+        assert_eq!(proxy_for(&env, "http://example.com/"), None);
+    }
+}
@@ -2,29 +2,65 @@ use std::{io::Write, str};
 
 use bytes::Bytes;
 
-use crate::{Error, Headers, Method, Status, StatusCode, Version, utils::find_slice};
+use crate::{
+    Challenge, Error, Headers, Method, Status, StatusCode, Timings, Version, utils::find_slice,
+};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, Clone)]
 pub struct Response {
     pub status: Status,
     pub headers: Headers,
     pub method: Method,
     pub body: Bytes,
+    pub(crate) timings: Timings,
 }
 
+/// Responses compare by what was received; the timings of the exchange
+/// that produced them are left out.
+impl PartialEq for Response {
+    fn eq(&self, other: &Self) -> bool {
+        self.status == other.status
+            && self.headers == other.headers
+            && self.method == other.method
+            && self.body == other.body
+    }
+}
+
+impl Eq for Response {}
+
 impl Response {
+    /// Parses a response head in place: the status line and each field are
+    /// taken as slices of `header`, only the values kept in the response
+    /// are copied.
     pub fn from_header(header: &[u8]) -> Result<Response, Error> {
-        let mut header = str::from_utf8(header)?.splitn(2, '\n');
-
-        let status = header.next().ok_or(Error::EmptyStatus)?.parse()?;
-        let headers = header.next().ok_or(Error::HeadersErr)?.parse()?;
-        let body = Bytes::new();
+        let (status_line, fields) = match header.iter().position(|&b| b == b'\n') {
+            Some(pos) => (&header[..pos], &header[pos + 1..]),
+            None if header.is_empty() => return Err(Error::EmptyStatus),
+            None => return Err(Error::HeadersErr),
+        };
+
+        let status = parse_status(status_line)?;
+        let mut headers = Headers::new();
+        for line in fields.split(|&b| b == b'\n').map(<[u8]>::trim_ascii) {
+            if line.is_empty() {
+                continue;
+            }
+            let idx = line
+                .iter()
+                .position(|&b| b == b':')
+                .ok_or(Error::ParseHeaders)?;
+            headers.append(
+                str::from_utf8(&line[..idx])?,
+                str::from_utf8(line[idx + 1..].trim_ascii())?,
+            )?;
+        }
 
         Ok(Response {
             status,
             headers,
             method: Method::Get,
-            body,
+            body: Bytes::new(),
+            timings: Timings::default(),
         })
     }
 
@@ -64,10 +100,28 @@ impl Response {
         self.headers.get(value)
     }
 
+    /// Challenges of the `WWW-Authenticate` header.
+    pub fn challenges(&self) -> Vec<Challenge> {
+        self.header("WWW-Authenticate")
+            .map_or_else(Vec::new, |value| Challenge::parse_list(&value))
+    }
+
+    /// Challenges of the `Proxy-Authenticate` header.
+    pub fn proxy_challenges(&self) -> Vec<Challenge> {
+        self.header("Proxy-Authenticate")
+            .map_or_else(Vec::new, |value| Challenge::parse_list(&value))
+    }
+
     pub fn content_len(&self) -> Option<usize> {
         self.headers().content_length()
     }
 
+    /// Where the time of the request went. Zero for responses that were not
+    /// received over a connection.
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
     pub fn body(&self) -> Bytes {
         self.body.clone()
     }
@@ -91,6 +145,20 @@ impl Response {
     }
 }
 
+// status-line = HTTP-version SP status-code SP reason-phrase CRLF
+fn parse_status(line: &[u8]) -> Result<Status, Error> {
+    let mut parts = line.trim_ascii().splitn(3, |&b| b == b' ');
+
+    let version: Version = str::from_utf8(parts.next().ok_or(Error::EmptyVersion)?)?.parse()?;
+    let code: StatusCode = str::from_utf8(parts.next().ok_or(Error::EmptyStatus)?)?.parse()?;
+    let reason = match parts.next() {
+        Some(reason) => str::from_utf8(reason)?,
+        None => code.reason().unwrap_or("Unknown"),
+    };
+
+    Status::try_from((version, code, reason))
+}
+
 #[cfg(test)]
 mod tests {
     use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
@@ -114,6 +182,48 @@ mod tests {
         Response::from_header(RESPONSE_H).unwrap();
     }
 
+    #[test]
+    fn res_from_head_fields() {
+        let res = Response::from_header(
+            b"HTTP/1.0 404 Not Found\n\
+              Via:  1.0 a \n\
+              Via: 1.1 b\r\n\
+              X-Empty:\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(res.version(), Version::Http10);
+        assert_eq!(res.status_code().as_u16(), 404);
+        assert_eq!(res.reason(), "Not Found");
+        assert_eq!(res.header("via").as_deref(), Some("1.0 a, 1.1 b"));
+        assert_eq!(res.header("x-empty").as_deref(), Some(""));
+        assert_eq!(
+            Response::from_header(b"HTTP/1.1 200 OK\r\nbroken\r\n"),
+            Err(Error::ParseHeaders)
+        );
+        assert!(Response::from_header(b"HTTP/1.1 200 OK\r\nX: \xff\r\n").is_err());
+        let res = Response::from_header(
+            b"HTTP/1.1 200 OK\r\n\
+              Content-Length: 5\r\n\
+              Content-Length: 5\r\n\
+              Set-Cookie: a=1\r\n\
+              Set-Cookie: b=2\r\n",
+        )
+        .unwrap();
+        assert_eq!(res.content_len(), Some(5));
+        assert_eq!(res.headers().get_all("set-cookie"), vec!["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn res_eq_ignores_timings() {
+        let res = Response::from_header(RESPONSE_H).unwrap();
+        let mut timed = res.clone();
+        timed.timings.connect = std::time::Duration::from_millis(5);
+        assert_eq!(res, timed);
+        timed.headers.insert("Age", "1");
+        assert_ne!(res, timed);
+    }
+
     #[test]
     fn res_try_from() {
         let mut writer = Vec::new();
@@ -167,6 +277,23 @@ mod tests {
         assert_eq!(res.headers(), &headers);
     }
 
+    #[test]
+    fn res_challenges() {
+        let res = Response::from_header(
+            b"HTTP/1.1 401 Unauthorized\r\n\
+              WWW-Authenticate: Digest realm=\"r\", nonce=\"n\"\r\n\
+              WWW-Authenticate: Basic realm=\"r\"\r\n",
+        )
+        .unwrap();
+        let challenges = res.challenges();
+
+        assert_eq!(challenges.len(), 2);
+        assert!(challenges[0].has_scheme("Digest"));
+        assert_eq!(challenges[0].param("nonce"), Some("n"));
+        assert!(challenges[1].has_scheme("Basic"));
+        assert!(res.proxy_challenges().is_empty());
+    }
+
     #[test]
     fn res_content_len() {
         let mut writer = Vec::with_capacity(101);
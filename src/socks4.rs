@@ -0,0 +1,123 @@
+// SOCKS 4 https://www.openssh.com/txt/socks4.protocol
+// SOCKS 4A https://www.openssh.com/txt/socks4a.protocol
+//
+//     +----+----+----+----+----+----+----+----+----+----+....+----+
+//     | VN | CD | DSTPORT |      DSTIP        | USERID       |NULL|
+//     +----+----+----+----+----+----+----+----+----+----+....+----+
+//        1    1      2              4           variable       1
+//
+//     +----+----+----+----+----+----+----+----+
+//     | VN | CD | DSTPORT |      DSTIP        |
+//     +----+----+----+----+----+----+----+----+
+//        1    1      2              4
+use std::net::{IpAddr, Ipv4Addr};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use url::{Host, Url};
+
+use crate::Error;
+
+const SOCKS4_VERSION: u8 = 4;
+const SOCKS4_COMMAND_CONNECT: u8 = 1;
+const SOCKS4_REPLY_VERSION: u8 = 0;
+const SOCKS4_REQUEST_GRANTED: u8 = 90;
+
+/// Performs a SOCKS4 CONNECT handshake on `stream`. When `remote_dns` is set
+/// (SOCKS4A) a domain name target is sent to the proxy to resolve, otherwise
+/// it is resolved locally to an IPv4 address.
+pub(crate) async fn handshake<S>(
+    stream: &mut S,
+    target: &Url,
+    user_id: &str,
+    remote_dns: bool,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let msg = request(target, user_id, remote_dns)?;
+    stream.write_all(&msg).await?;
+    stream.flush().await?;
+    let mut reply = [0u8; 8];
+    stream.read_exact(&mut reply).await?;
+    match (reply[0], reply[1]) {
+        (SOCKS4_REPLY_VERSION, SOCKS4_REQUEST_GRANTED) => Ok(()),
+        (SOCKS4_REPLY_VERSION, code) => Err(Error::Socks4Rejected(code)),
+        (version, _) => Err(Error::Socks4Version(version)),
+    }
+}
+
+fn request(target: &Url, user_id: &str, remote_dns: bool) -> Result<Vec<u8>, Error> {
+    let port = target.port_or_known_default().ok_or(Error::SocketAddr)?;
+    let (ip, domain) = match target.host().ok_or(Error::EmptyHost)? {
+        Host::Ipv4(ip) => (ip, None),
+        Host::Domain(domain) if remote_dns => (Ipv4Addr::new(0, 0, 0, 1), Some(domain)),
+        _ => match target.socket_addrs(|| None)?.iter().find(|a| a.is_ipv4()) {
+            Some(addr) => match addr.ip() {
+                IpAddr::V4(ip) => (ip, None),
+                IpAddr::V6(_) => return Err(Error::Socks4Ipv4),
+            },
+            None => return Err(Error::Socks4Ipv4),
+        },
+    };
+    let mut msg = vec![SOCKS4_VERSION, SOCKS4_COMMAND_CONNECT];
+    msg.extend_from_slice(&port.to_be_bytes());
+    msg.extend_from_slice(&ip.octets());
+    msg.extend_from_slice(user_id.as_bytes());
+    msg.push(0);
+    if let Some(domain) = domain {
+        msg.extend_from_slice(domain.as_bytes());
+        msg.push(0);
+    }
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+
+    #[test]
+    fn socks4_request_ip() {
+        let url = Url::parse("http://127.0.0.1:8080").unwrap();
+        assert_eq!(
+            request(&url, "user", false).unwrap(),
+            vec![4, 1, 0x1f, 0x90, 127, 0, 0, 1, b'u', b's', b'e', b'r', 0]
+        );
+    }
+
+    #[test]
+    fn socks4a_request_domain() {
+        let url = Url::parse("http://example.com").unwrap();
+        let mut expected = vec![4, 1, 0, 80, 0, 0, 0, 1, 0];
+        expected.extend_from_slice(b"example.com\0");
+        assert_eq!(request(&url, "", true).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn socks4_handshake() {
+        let url = Url::parse("http://127.0.0.1:80").unwrap();
+        let (mut client, mut server) = duplex(64);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 9];
+            server.read_exact(&mut buf).await.unwrap();
+            server.write_all(&[0, 90, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        });
+        handshake(&mut client, &url, "", false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks4_handshake_rejected() {
+        let url = Url::parse("http://127.0.0.1:80").unwrap();
+        let (mut client, mut server) = duplex(64);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 9];
+            server.read_exact(&mut buf).await.unwrap();
+            server.write_all(&[0, 91, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        });
+        assert_eq!(
+            handshake(&mut client, &url, "", false).await,
+            Err(Error::Socks4Rejected(91))
+        );
+    }
+}
@@ -0,0 +1,380 @@
+// Server-sent events https://html.spec.whatwg.org/multipage/server-sent-events.html
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::{Buf, BytesMut};
+use futures::{FutureExt, Stream, future::BoxFuture};
+
+use crate::{Body, ClientBuilder, Error};
+
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+const MAX_LINE_LENGTH: usize = 1 << 20;
+
+type NextEvent = BoxFuture<'static, (Reader, Option<Result<Event, Error>>)>;
+
+/// A dispatched server-sent event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Event type, `message` when the server named none.
+    pub event: String,
+    pub data: String,
+    /// Last event ID seen on the stream when the event was dispatched.
+    pub id: Option<String>,
+}
+
+/// Consumer of a `text/event-stream` endpoint, yielding events as a [`Stream`].
+///
+/// When the connection ends or fails the request is sent again after the
+/// retry interval, with `Last-Event-ID` set to the last received ID. Failed
+/// attempts are yielded as errors and retried on the next poll. Redirects are
+/// followed on every attempt. A response other than `200 text/event-stream`
+/// or a line longer than 1 MiB ends the stream with an error, and `204 No
+/// Content` ends it without one.
+pub struct EventSource {
+    reader: Option<Reader>,
+    pending: Option<NextEvent>,
+}
+
+impl EventSource {
+    pub(crate) fn new(builder: ClientBuilder) -> EventSource {
+        EventSource {
+            reader: Some(Reader {
+                builder,
+                body: None,
+                connected: false,
+                closed: false,
+                buf: BytesMut::new(),
+                parser: Parser::default(),
+                retry: DEFAULT_RETRY,
+            }),
+            pending: None,
+        }
+    }
+
+    /// ID of the last event received, sent as `Last-Event-ID` on reconnect.
+    /// `None` while an event is being read.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.reader
+            .as_ref()
+            .and_then(|reader| reader.parser.last_event_id.as_deref())
+    }
+
+    /// Reconnection delay, updated by `retry` fields of the server.
+    pub fn retry(&self) -> Option<Duration> {
+        self.reader.as_ref().map(|reader| reader.retry)
+    }
+}
+
+impl std::fmt::Debug for EventSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("EventSource")
+            .field("last_event_id", &self.last_event_id())
+            .field("retry", &self.retry())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Stream for EventSource {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.pending.is_none() {
+            let Some(mut reader) = this.reader.take() else {
+                return Poll::Ready(None);
+            };
+            this.pending = Some(
+                async move {
+                    let item = reader.next_event().await;
+                    (reader, item)
+                }
+                .boxed(),
+            );
+        }
+        let Some(pending) = &mut this.pending else {
+            return Poll::Ready(None);
+        };
+        let (reader, item) = futures::ready!(pending.poll_unpin(cx));
+        this.pending = None;
+        this.reader = Some(reader);
+        Poll::Ready(item)
+    }
+}
+
+struct Reader {
+    builder: ClientBuilder,
+    body: Option<Body>,
+    /// Whether a connection was opened before, so the next one is a retry.
+    connected: bool,
+    closed: bool,
+    buf: BytesMut,
+    parser: Parser,
+    retry: Duration,
+}
+
+impl Reader {
+    async fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        loop {
+            if self.closed {
+                return None;
+            }
+            if self.body.is_none() {
+                if self.connected {
+                    tokio::time::sleep(self.retry).await;
+                }
+                self.connected = true;
+                match self.connect().await {
+                    Ok(Some(body)) => self.body = Some(body),
+                    Ok(None) => {
+                        self.closed = true;
+                        return None;
+                    }
+                    Err(
+                        err @ (Error::EventSourceStatus(_)
+                        | Error::EventSourceType(_)
+                        | Error::MaxRedirects),
+                    ) => {
+                        self.closed = true;
+                        return Some(Err(err));
+                    }
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            while let Some(line) = next_line(&mut self.buf) {
+                if let Some(retry) = self.parser.line(&line) {
+                    self.retry = retry;
+                }
+                if line.is_empty()
+                    && let Some(event) = self.parser.dispatch()
+                {
+                    return Some(Ok(event));
+                }
+            }
+            if self.buf.len() > MAX_LINE_LENGTH {
+                self.disconnect();
+                self.closed = true;
+                return Some(Err(Error::EventSourceLine(MAX_LINE_LENGTH)));
+            }
+            let chunk = match &mut self.body {
+                Some(body) => body.chunk().await,
+                None => continue,
+            };
+            match chunk {
+                Ok(Some(chunk)) => self.buf.extend_from_slice(&chunk),
+                Ok(None) => self.disconnect(),
+                Err(err) => {
+                    self.disconnect();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+
+    /// Opens the stream, `None` when the server asked not to reconnect.
+    async fn connect(&mut self) -> Result<Option<Body>, Error> {
+        let mut builder = self
+            .builder
+            .clone()
+            .header("Accept", "text/event-stream")
+            .header("Cache-Control", "no-cache");
+        if let Some(id) = &self.parser.last_event_id {
+            builder = builder.header("Last-Event-ID", id);
+        }
+        let mut redirects = 0;
+        let (response, body) = loop {
+            let url = builder.url.clone();
+            let (response, body) = builder.clone().build().await?.send_streaming().await?;
+            if response.status_code().is_redirect()
+                && let (Some(url), Some(location)) = (url, response.header("Location"))
+            {
+                redirects += 1;
+                if redirects >= builder.config.max_redirects {
+                    return Err(Error::MaxRedirects);
+                }
+                builder = builder.redirect_to(&url.join(&location)?);
+                continue;
+            }
+            break (response, body);
+        };
+        match response.status_code().as_u16() {
+            200 => {}
+            204 => return Ok(None),
+            code => return Err(Error::EventSourceStatus(code)),
+        }
+        let content_type = response.header("Content-Type").unwrap_or_default();
+        if !content_type
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/event-stream"))
+        {
+            return Err(Error::EventSourceType(content_type));
+        }
+        self.parser.bom = true;
+        Ok(Some(body))
+    }
+
+    /// Drops the connection and the partly received event.
+    fn disconnect(&mut self) {
+        self.body = None;
+        self.buf.clear();
+        self.parser.reset();
+    }
+}
+
+/// Takes the next line ending in CRLF, LF or CR from `buf`.
+fn next_line(buf: &mut BytesMut) -> Option<String> {
+    let end = buf.iter().position(|b| *b == b'\n' || *b == b'\r')?;
+    // A CR at the end of the buffer may be the first half of a CRLF.
+    if buf[end] == b'\r' && end + 1 == buf.len() {
+        return None;
+    }
+    let line = String::from_utf8_lossy(&buf[..end]).into_owned();
+    let eol = if buf[end] == b'\r' && buf[end + 1] == b'\n' {
+        2
+    } else {
+        1
+    };
+    buf.advance(end + eol);
+    Some(line)
+}
+
+#[derive(Debug, Default)]
+struct Parser {
+    event: String,
+    data: String,
+    last_event_id: Option<String>,
+    /// A byte order mark may start the next line.
+    bom: bool,
+}
+
+impl Parser {
+    /// Processes a field line, returning a new reconnection delay.
+    fn line(&mut self, line: &str) -> Option<Duration> {
+        let line = match std::mem::take(&mut self.bom) {
+            true => line.strip_prefix('\u{feff}').unwrap_or(line),
+            false => line,
+        };
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => {
+                self.last_event_id = Some(value.to_string()).filter(|id| !id.is_empty());
+            }
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                return value.parse().ok().map(Duration::from_millis);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Ends the current event on an empty line.
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = std::mem::take(&mut self.event);
+        let mut data = std::mem::take(&mut self.data);
+        if data.is_empty() {
+            return None;
+        }
+        data.pop();
+        Some(Event {
+            event: if event.is_empty() {
+                "message".to_string()
+            } else {
+                event
+            },
+            data,
+            id: self.last_event_id.clone(),
+        })
+    }
+
+    fn reset(&mut self) {
+        self.event.clear();
+        self.data.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> (Vec<Event>, Option<Duration>) {
+        let mut buf = BytesMut::from(input);
+        let mut parser = Parser {
+            bom: true,
+            ..Parser::default()
+        };
+        let mut events = Vec::new();
+        let mut retry = None;
+        while let Some(line) = next_line(&mut buf) {
+            retry = parser.line(&line).or(retry);
+            if line.is_empty() {
+                events.extend(parser.dispatch());
+            }
+        }
+        (events, retry)
+    }
+
+    #[test]
+    fn parse_fields() {
+        let (events, retry) = parse(
+            "\u{feff}: comment\r\n\
+             data: first\r\n\
+             data:second\r\n\
+             id: 1\r\n\
+             \r\n\
+             event: update\n\
+             data\n\
+             retry: 1500\n\
+             \n\
+             id\rdata: x\r\r\n",
+        );
+        assert_eq!(
+            events,
+            vec![
+                Event {
+                    event: "message".into(),
+                    data: "first\nsecond".into(),
+                    id: Some("1".into()),
+                },
+                Event {
+                    event: "update".into(),
+                    data: String::new(),
+                    id: Some("1".into()),
+                },
+                Event {
+                    event: "message".into(),
+                    data: "x".into(),
+                    id: None,
+                },
+            ]
+        );
+        assert_eq!(retry, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn incomplete_lines() {
+        let mut buf = BytesMut::from("data: a\r");
+        assert_eq!(next_line(&mut buf), None);
+        buf.extend_from_slice(b"\ndata");
+        assert_eq!(next_line(&mut buf).as_deref(), Some("data: a"));
+        assert_eq!(next_line(&mut buf), None);
+        let (events, _) = parse("data: no blank line");
+        assert!(events.is_empty());
+        let (events, retry) = parse("retry: 1s\n\n");
+        assert!(events.is_empty());
+        assert_eq!(retry, None);
+    }
+}
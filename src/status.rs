@@ -222,7 +222,7 @@ mod tests {
             assert!(StatusCode(i).is_info())
         }
 
-        for i in (0..1000).filter(|&i| i < 100 || i >= 200) {
+        for i in (0..1000).filter(|i| !(100..200).contains(i)) {
             assert!(!StatusCode(i).is_info())
         }
     }
@@ -233,7 +233,7 @@ mod tests {
             assert!(StatusCode(i).is_success())
         }
 
-        for i in (0..1000).filter(|&i| i < 200 || i >= 300) {
+        for i in (0..1000).filter(|i| !(200..300).contains(i)) {
             assert!(!StatusCode(i).is_success())
         }
     }
@@ -244,7 +244,7 @@ mod tests {
             assert!(StatusCode(i).is_redirect())
         }
 
-        for i in (0..1000).filter(|&i| i < 300 || i >= 400) {
+        for i in (0..1000).filter(|i| !(300..400).contains(i)) {
             assert!(!StatusCode(i).is_redirect())
         }
     }
@@ -255,7 +255,7 @@ mod tests {
             assert!(StatusCode(i).is_client_err())
         }
 
-        for i in (0..1000).filter(|&i| i < 400 || i >= 500) {
+        for i in (0..1000).filter(|i| !(400..500).contains(i)) {
             assert!(!StatusCode(i).is_client_err())
         }
     }
@@ -266,7 +266,7 @@ mod tests {
             assert!(StatusCode(i).is_server_err())
         }
 
-        for i in (0..1000).filter(|&i| i < 500 || i >= 600) {
+        for i in (0..1000).filter(|i| !(500..600).contains(i)) {
             assert!(!StatusCode(i).is_server_err())
         }
     }
@@ -0,0 +1,757 @@
+// In-process stand-ins for servers and proxies, for tests without network
+use std::{
+    fmt::Write,
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use url::Url;
+
+use crate::{Error, Headers, Method, StatusCode, buf_stream::find_head_end};
+
+const MAX_HEAD_LENGTH: usize = 64 * 1024;
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTH: u8 = 0;
+const SOCKS5_PASSWORD_AUTH: u8 = 2;
+const SOCKS5_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const SOCKS5_CONNECTION_REFUSED: u8 = 5;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 7;
+const SOCKS4_VERSION: u8 = 4;
+const SOCKS4_GRANTED: u8 = 90;
+const SOCKS4_REJECTED: u8 = 91;
+
+/// Response scripted for a [`TestServer`]. Well-formed responses carry a
+/// `Content-Length`, or are chunked.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+    chunk_size: Option<usize>,
+    raw: Option<Bytes>,
+    delay: Duration,
+    pause: Duration,
+    truncate: Option<usize>,
+    echo: bool,
+}
+
+impl Reply {
+    pub fn new(status: u16) -> Reply {
+        Reply {
+            status,
+            headers: Vec::new(),
+            body: Bytes::new(),
+            chunk_size: None,
+            raw: None,
+            delay: Duration::ZERO,
+            pause: Duration::ZERO,
+            truncate: None,
+            echo: false,
+        }
+    }
+
+    /// Sends `bytes` as they are instead of a response, to script malformed
+    /// ones.
+    pub fn raw<B: Into<Bytes>>(bytes: B) -> Reply {
+        Reply {
+            raw: Some(bytes.into()),
+            ..Reply::new(200)
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Reply {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<B: Into<Bytes>>(mut self, body: B) -> Reply {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends the body with chunked transfer coding, in chunks of `size` bytes.
+    pub fn chunked(mut self, size: usize) -> Reply {
+        self.chunk_size = Some(size.max(1));
+        self
+    }
+
+    /// Waits before sending anything.
+    pub fn delay(mut self, delay: Duration) -> Reply {
+        self.delay = delay;
+        self
+    }
+
+    /// Waits before each write after the head: the body, or every chunk.
+    pub fn pause(mut self, pause: Duration) -> Reply {
+        self.pause = pause;
+        self
+    }
+
+    /// Closes the connection after the first `len` bytes of the response.
+    pub fn truncate(mut self, len: usize) -> Reply {
+        self.truncate = Some(len);
+        self
+    }
+
+    /// Keeps the connection open after the response and sends back whatever
+    /// the client writes, to script protocol upgrades.
+    pub fn echo(mut self) -> Reply {
+        self.echo = true;
+        self
+    }
+
+    /// The response in the pieces it is written in.
+    fn pieces(&self) -> Vec<Bytes> {
+        let mut pieces = match &self.raw {
+            Some(raw) => vec![raw.clone()],
+            None => self.message(),
+        };
+        if let Some(mut left) = self.truncate {
+            for piece in &mut pieces {
+                piece.truncate(left);
+                left -= piece.len();
+            }
+            pieces.retain(|piece| !piece.is_empty());
+        }
+        pieces
+    }
+
+    fn message(&self) -> Vec<Bytes> {
+        let reason = StatusCode::from_u16(self.status)
+            .ok()
+            .and_then(|code| code.reason())
+            .unwrap_or("Unknown");
+        let mut head = format!("HTTP/1.1 {} {reason}\r\n", self.status);
+        for (name, value) in &self.headers {
+            let _ = write!(head, "{name}: {value}\r\n");
+        }
+        let Some(size) = self.chunk_size else {
+            let _ = write!(head, "Content-Length: {}\r\n\r\n", self.body.len());
+            return [Bytes::from(head), self.body.clone()]
+                .into_iter()
+                .filter(|piece| !piece.is_empty())
+                .collect();
+        };
+        head.push_str("Transfer-Encoding: chunked\r\n\r\n");
+        let mut pieces = vec![Bytes::from(head)];
+        for chunk in self.body.chunks(size) {
+            let mut piece = format!("{:x}\r\n", chunk.len()).into_bytes();
+            piece.extend_from_slice(chunk);
+            piece.extend_from_slice(b"\r\n");
+            pieces.push(piece.into());
+        }
+        pieces.push(Bytes::from_static(b"0\r\n\r\n"));
+        pieces
+    }
+
+    async fn write(&self, stream: &mut TcpStream) -> Result<(), Error> {
+        tokio::time::sleep(self.delay).await;
+        for (index, piece) in self.pieces().iter().enumerate() {
+            if index > 0 {
+                tokio::time::sleep(self.pause).await;
+            }
+            stream.write_all(piece).await?;
+            stream.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Request received by a [`TestServer`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceivedRequest {
+    pub method: Method,
+    /// Request target as sent, the path and query in origin form.
+    pub target: String,
+    pub headers: Headers,
+    pub body: Bytes,
+}
+
+/// HTTP/1.1 server answering with scripted replies, one per connection.
+///
+/// Replies are sent in order for the requests as they arrive, and the last
+/// one is repeated once the script is used up. The connection is closed after
+/// each reply. The server stops when it is dropped.
+#[derive(Debug)]
+pub struct TestServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+    peak: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start(replies: Vec<Reply>) -> Result<TestServer, Error> {
+        TestServer::start_with(move |index, _| {
+            replies
+                .get(index)
+                .or(replies.last())
+                .cloned()
+                .unwrap_or_else(|| Reply::new(404))
+        })
+        .await
+    }
+
+    /// Starts a server answering each request with the reply `handler` makes
+    /// for it, to script replies that depend on the request.
+    pub async fn with_handler<F>(handler: F) -> Result<TestServer, Error>
+    where
+        F: Fn(&ReceivedRequest) -> Reply + Send + Sync + 'static,
+    {
+        TestServer::start_with(move |_, request| handler(request)).await
+    }
+
+    async fn start_with<F>(handler: F) -> Result<TestServer, Error>
+    where
+        F: Fn(usize, &ReceivedRequest) -> Reply + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let active = Arc::new(AtomicUsize::new(0));
+        let handler = Arc::new(handler);
+        let (received, highest) = (requests.clone(), peak.clone());
+        let task = serve(listener, move |mut stream| {
+            let (requests, handler) = (received.clone(), handler.clone());
+            let (active, peak) = (active.clone(), highest.clone());
+            async move {
+                let request = read_request(&mut stream).await?;
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                let reply = {
+                    let mut requests = requests.lock().unwrap_or_else(|err| err.into_inner());
+                    let reply = handler(requests.len(), &request);
+                    requests.push(request);
+                    reply
+                };
+                let written = reply.write(&mut stream).await;
+                active.fetch_sub(1, Ordering::SeqCst);
+                written?;
+                if reply.echo {
+                    let (mut reader, mut writer) = stream.split();
+                    tokio::io::copy(&mut reader, &mut writer).await?;
+                }
+                Ok(())
+            }
+        });
+        Ok(TestServer {
+            addr,
+            requests,
+            peak,
+            task,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL of `path` on the server, like `http://127.0.0.1:8080/path`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    /// Requests received so far, in the order they arrived.
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Highest number of requests answered at once so far, from the time a
+    /// request is read until its reply is written.
+    pub fn peak_connections(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// SOCKS5 and SOCKS4(A) proxy with optional username and password
+/// authentication. SOCKS4 clients authenticate with the username as user ID.
+/// The proxy stops when it is dropped.
+#[derive(Debug)]
+pub struct SocksProxy {
+    addr: SocketAddr,
+    auth: Option<(String, String)>,
+    tunnels: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+}
+
+impl SocksProxy {
+    pub async fn start() -> Result<SocksProxy, Error> {
+        SocksProxy::start_with(None).await
+    }
+
+    pub async fn with_auth(username: &str, password: &str) -> Result<SocksProxy, Error> {
+        SocksProxy::start_with(Some((username.to_string(), password.to_string()))).await
+    }
+
+    async fn start_with(auth: Option<(String, String)>) -> Result<SocksProxy, Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let tunnels = Arc::new(AtomicUsize::new(0));
+        let (counter, credentials) = (tunnels.clone(), auth.clone());
+        let task = serve(listener, move |stream| {
+            socks_connection(stream, credentials.clone(), counter.clone())
+        });
+        Ok(SocksProxy {
+            addr,
+            auth,
+            tunnels,
+            task,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL of the proxy for `scheme`, one of `socks5`, `socks5h`, `socks4`
+    /// and `socks4a`, with the credentials when the proxy requires them.
+    pub fn url(&self, scheme: &str) -> String {
+        match &self.auth {
+            Some((username, _)) if scheme.starts_with("socks4") => {
+                format!("{scheme}://{username}@{}", self.addr)
+            }
+            Some((username, password)) => {
+                format!("{scheme}://{username}:{password}@{}", self.addr)
+            }
+            None => format!("{scheme}://{}", self.addr),
+        }
+    }
+
+    /// Tunnels opened so far.
+    pub fn tunnels(&self) -> usize {
+        self.tunnels.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for SocksProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// HTTP proxy forwarding requests in absolute form and opening tunnels for
+/// `CONNECT`, with optional `Basic` authentication. The proxy stops when it
+/// is dropped.
+#[derive(Debug)]
+pub struct HttpProxy {
+    addr: SocketAddr,
+    auth: Option<(String, String)>,
+    tunnels: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+}
+
+impl HttpProxy {
+    pub async fn start() -> Result<HttpProxy, Error> {
+        HttpProxy::start_with(None).await
+    }
+
+    pub async fn with_auth(username: &str, password: &str) -> Result<HttpProxy, Error> {
+        HttpProxy::start_with(Some((username.to_string(), password.to_string()))).await
+    }
+
+    async fn start_with(auth: Option<(String, String)>) -> Result<HttpProxy, Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let tunnels = Arc::new(AtomicUsize::new(0));
+        let authorization = auth
+            .as_ref()
+            .map(|(username, password)| STANDARD.encode(format!("{username}:{password}")));
+        let counter = tunnels.clone();
+        let task = serve(listener, move |stream| {
+            http_proxy_connection(stream, authorization.clone(), counter.clone())
+        });
+        Ok(HttpProxy {
+            addr,
+            auth,
+            tunnels,
+            task,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL of the proxy, with the credentials when it requires them.
+    pub fn url(&self) -> String {
+        match &self.auth {
+            Some((username, password)) => format!("http://{username}:{password}@{}", self.addr),
+            None => format!("http://{}", self.addr),
+        }
+    }
+
+    /// Requests forwarded and tunnels opened so far.
+    pub fn tunnels(&self) -> usize {
+        self.tunnels.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for HttpProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Accepts connections on `listener` and handles each in a task of its own.
+fn serve<F, Fut>(listener: TcpListener, handle: F) -> JoinHandle<()>
+where
+    F: Fn(TcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let connection = handle(stream);
+            tokio::spawn(async move {
+                let _ = connection.await;
+            });
+        }
+    })
+}
+
+/// Reads a request head into `buf`, returning its length.
+async fn read_head(stream: &mut TcpStream, buf: &mut BytesMut) -> Result<usize, Error> {
+    loop {
+        if let Some(len) = find_head_end(buf, 0) {
+            return Ok(len);
+        }
+        if buf.len() > MAX_HEAD_LENGTH || stream.read_buf(buf).await? == 0 {
+            return Err(Error::HeaderIncomplete);
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<ReceivedRequest, Error> {
+    let mut buf = BytesMut::with_capacity(1024);
+    let head_len = read_head(stream, &mut buf).await?;
+    let head = buf.split_to(head_len);
+    let (request_line, headers) = std::str::from_utf8(&head)?
+        .split_once("\r\n")
+        .ok_or(Error::HeadersErr)?;
+    let mut parts = request_line.split(' ');
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next());
+    let headers: Headers = headers.parse()?;
+    let body_len = headers.content_length().unwrap_or_default();
+    while buf.len() < body_len {
+        if stream.read_buf(&mut buf).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+    Ok(ReceivedRequest {
+        method: Method::from(method),
+        target: target.unwrap_or_default().to_string(),
+        headers,
+        body: buf.split_to(body_len).freeze(),
+    })
+}
+
+async fn socks_connection(
+    mut stream: TcpStream,
+    auth: Option<(String, String)>,
+    tunnels: Arc<AtomicUsize>,
+) -> Result<(), Error> {
+    let target = match stream.read_u8().await? {
+        SOCKS5_VERSION => socks5_handshake(&mut stream, auth).await?,
+        SOCKS4_VERSION => socks4_handshake(&mut stream, auth).await?,
+        version => return Err(Error::Socks4Version(version)),
+    };
+    let Some(mut upstream) = target else {
+        return Ok(());
+    };
+    tunnels.fetch_add(1, Ordering::Relaxed);
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+    Ok(())
+}
+
+/// Answers a SOCKS5 greeting after its version byte, returning the connection
+/// to the target when it was opened.
+async fn socks5_handshake(
+    stream: &mut TcpStream,
+    auth: Option<(String, String)>,
+) -> Result<Option<TcpStream>, Error> {
+    let mut methods = vec![0u8; stream.read_u8().await? as usize];
+    stream.read_exact(&mut methods).await?;
+    let method = match auth {
+        Some(_) => SOCKS5_PASSWORD_AUTH,
+        None => SOCKS5_NO_AUTH,
+    };
+    if !methods.contains(&method) {
+        stream
+            .write_all(&[SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHOD])
+            .await?;
+        return Ok(None);
+    }
+    stream.write_all(&[SOCKS5_VERSION, method]).await?;
+    if let Some((username, password)) = auth {
+        let _version = stream.read_u8().await?;
+        let mut user = vec![0u8; stream.read_u8().await? as usize];
+        stream.read_exact(&mut user).await?;
+        let mut pass = vec![0u8; stream.read_u8().await? as usize];
+        stream.read_exact(&mut pass).await?;
+        let accepted = user == username.as_bytes() && pass == password.as_bytes();
+        stream.write_all(&[1, u8::from(!accepted)]).await?;
+        if !accepted {
+            return Ok(None);
+        }
+    }
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            IpAddr::from(ip).to_string()
+        }
+        3 => {
+            let mut domain = vec![0u8; stream.read_u8().await? as usize];
+            stream.read_exact(&mut domain).await?;
+            String::from_utf8(domain)?
+        }
+        _ => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+    };
+    let port = stream.read_u16().await?;
+    let reply = |code: u8| [SOCKS5_VERSION, code, 0, 1, 0, 0, 0, 0, 0, 0];
+    if request[1] != 1 {
+        stream
+            .write_all(&reply(SOCKS5_COMMAND_NOT_SUPPORTED))
+            .await?;
+        return Ok(None);
+    }
+    match TcpStream::connect((host.as_str(), port)).await {
+        Ok(upstream) => {
+            stream.write_all(&reply(0)).await?;
+            Ok(Some(upstream))
+        }
+        Err(_) => {
+            stream.write_all(&reply(SOCKS5_CONNECTION_REFUSED)).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Answers a SOCKS4 or SOCKS4A request after its version byte, returning the
+/// connection to the target when it was opened.
+async fn socks4_handshake(
+    stream: &mut TcpStream,
+    auth: Option<(String, String)>,
+) -> Result<Option<TcpStream>, Error> {
+    let mut request = [0u8; 7];
+    stream.read_exact(&mut request).await?;
+    let port = u16::from_be_bytes([request[1], request[2]]);
+    let ip = Ipv4Addr::new(request[3], request[4], request[5], request[6]);
+    let user_id = read_cstr(stream).await?;
+    // SOCKS4A marks a domain name following the user ID with 0.0.0.x.
+    let host = match ip.octets() {
+        [0, 0, 0, last] if last != 0 => read_cstr(stream).await?,
+        _ => ip.to_string(),
+    };
+    let allowed = auth.is_none_or(|(username, _)| username == user_id);
+    let upstream = match (request[0], allowed) {
+        (1, true) => TcpStream::connect((host.as_str(), port)).await.ok(),
+        _ => None,
+    };
+    let code = match upstream {
+        Some(_) => SOCKS4_GRANTED,
+        None => SOCKS4_REJECTED,
+    };
+    stream.write_all(&[0, code, 0, 0, 0, 0, 0, 0]).await?;
+    Ok(upstream)
+}
+
+async fn read_cstr(stream: &mut TcpStream) -> Result<String, Error> {
+    let mut bytes = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            0 => return Ok(String::from_utf8(bytes)?),
+            byte => bytes.push(byte),
+        }
+    }
+}
+
+async fn http_proxy_connection(
+    mut stream: TcpStream,
+    authorization: Option<String>,
+    tunnels: Arc<AtomicUsize>,
+) -> Result<(), Error> {
+    let mut buf = BytesMut::with_capacity(1024);
+    let head_len = read_head(&mut stream, &mut buf).await?;
+    let head = std::str::from_utf8(&buf[..head_len])?.to_string();
+    let rest = buf.split_off(head_len);
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let (proxy_headers, headers): (Vec<&str>, Vec<&str>) = lines
+        .filter(|line| !line.is_empty())
+        .partition(|line| line.to_ascii_lowercase().starts_with("proxy-"));
+    if let Some(expected) = authorization {
+        let authorized = proxy_headers.iter().any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.eq_ignore_ascii_case("proxy-authorization")
+                    && value.trim() == format!("Basic {expected}")
+            })
+        });
+        if !authorized {
+            stream
+                .write_all(
+                    b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                      Proxy-Authenticate: Basic realm=\"netc\"\r\n\
+                      Content-Length: 0\r\n\r\n",
+                )
+                .await?;
+            return Ok(());
+        }
+    }
+    let (authority, forwarded) = match method {
+        "CONNECT" => (target.to_string(), None),
+        _ => {
+            let url = Url::parse(target)?;
+            let authority = format!(
+                "{}:{}",
+                url.host_str().unwrap_or_default(),
+                url.port_or_known_default().unwrap_or(80)
+            );
+            let mut origin_form = url.path().to_string();
+            if let Some(query) = url.query() {
+                origin_form = format!("{origin_form}?{query}");
+            }
+            let mut head = format!("{method} {origin_form} {version}\r\n");
+            for line in headers {
+                let _ = write!(head, "{line}\r\n");
+            }
+            head.push_str("\r\n");
+            (authority, Some(head))
+        }
+    };
+    let Ok(mut upstream) = TcpStream::connect(authority.as_str()).await else {
+        stream
+            .write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n")
+            .await?;
+        return Ok(());
+    };
+    tunnels.fetch_add(1, Ordering::Relaxed);
+    match forwarded {
+        Some(head) => upstream.write_all(head.as_bytes()).await?,
+        None => {
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await?
+        }
+    }
+    upstream.write_all(&rest).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+
+    async fn get(url: &str) -> Result<crate::Response, Error> {
+        Client::builder().get(url).build().await?.send().await
+    }
+
+    #[test]
+    fn reply_pieces() {
+        let reply = Reply::new(200).body("hello").chunked(2);
+        assert_eq!(
+            reply.pieces().concat(),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+              2\r\nhe\r\n2\r\nll\r\n1\r\no\r\n0\r\n\r\n"
+        );
+        let reply = Reply::new(404).body("gone").truncate(47);
+        assert_eq!(
+            reply.pieces(),
+            vec![
+                Bytes::from_static(b"HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\n"),
+                Bytes::from_static(b"go"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scripted_replies() {
+        let server = TestServer::start(vec![
+            Reply::new(200).body("chunked body").chunked(5),
+            Reply::raw("HTTP/1.1 OK\r\n\r\n"),
+            Reply::new(200).body("truncated").truncate(45),
+            Reply::new(204).delay(Duration::from_millis(50)),
+        ])
+        .await
+        .unwrap();
+        let response = get(&server.url("/a?b=1")).await.unwrap();
+        assert_eq!(response.text().unwrap(), "chunked body");
+        assert!(get(&server.url("/")).await.is_err());
+        assert!(get(&server.url("/")).await.is_err());
+        let url = server.url("/");
+        let slow = tokio::time::timeout(Duration::from_millis(10), get(&url));
+        assert!(slow.await.is_err());
+        let response = get(&url).await.unwrap();
+        assert_eq!(response.status_code().as_u16(), 204);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        assert_eq!(requests[0].method, Method::Get);
+        assert_eq!(requests[0].target, "/a?b=1");
+    }
+
+    #[tokio::test]
+    async fn handler_replies() {
+        let server = TestServer::with_handler(|request| {
+            Reply::new(200)
+                .body(request.target.clone())
+                .delay(Duration::from_millis(20))
+        })
+        .await
+        .unwrap();
+        let (a, b) = (server.url("/a"), server.url("/b"));
+        let (first, second) = tokio::join!(get(&a), get(&b));
+        assert_eq!(first.unwrap().text().unwrap(), "/a");
+        assert_eq!(second.unwrap().text().unwrap(), "/b");
+        assert_eq!(server.peak_connections(), 2);
+
+        let server = TestServer::start(vec![
+            Reply::raw("HTTP/1.1 101 Switching Protocols\r\nUpgrade: echo\r\n\r\n").echo(),
+        ])
+        .await
+        .unwrap();
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut head = [0u8; 51];
+        stream.read_exact(&mut head).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
@@ -0,0 +1,36 @@
+use std::{net::SocketAddr, time::Duration};
+
+/// Where the time of a request went, from opening the connection to the end
+/// of the body.
+///
+/// Phases that did not happen are zero: the connection phases when the
+/// connection was reused, and all of them for responses served from a cache
+/// or a cassette. With proxies, `dns` and `connect` are those of the first
+/// proxy, and `tls` covers every handshake made over the tunnel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub dns: Duration,
+    /// TCP connect.
+    pub connect: Duration,
+    /// SOCKS and `CONNECT` handshakes with the proxies.
+    pub proxy: Duration,
+    pub tls: Duration,
+    /// Writing the request.
+    pub send: Duration,
+    /// From the start of the request to the end of the response head.
+    pub first_byte: Duration,
+    /// Reading the body.
+    pub transfer: Duration,
+    pub total: Duration,
+    pub remote_addr: Option<SocketAddr>,
+    pub local_addr: Option<SocketAddr>,
+    /// Whether the request went over a connection opened for an earlier one.
+    pub reused: bool,
+}
+
+impl Timings {
+    /// Time spent opening the connection.
+    pub fn setup(&self) -> Duration {
+        self.dns + self.connect + self.proxy + self.tls
+    }
+}
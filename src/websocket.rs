@@ -0,0 +1,614 @@
+// The WebSocket Protocol https://datatracker.ietf.org/doc/html/rfc6455
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{Sink, Stream};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{BufStream, Error, HttpStream, Response};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const BUFFER_CAPACITY: usize = 8192;
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    Close(Option<CloseFrame>),
+}
+
+/// Status code and reason of a `Close` frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub fn new(code: u16, reason: &str) -> CloseFrame {
+        CloseFrame {
+            code,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Bytes,
+}
+
+/// Client side of a WebSocket connection.
+///
+/// Messages are received through [`Stream`] and sent through [`Sink`]. Pings
+/// are answered with pongs and a `Close` of the server is echoed while
+/// reading, so the stream has to be polled for the connection to make
+/// progress. The stream ends after the `Close` of the server.
+pub struct WebSocket<S = HttpStream> {
+    stream: S,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    fragments: Option<(u8, BytesMut)>,
+    frame_size: Option<usize>,
+    max_message_size: usize,
+    protocol: Option<String>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocket<S> {
+    /// Wraps a stream that already completed the opening handshake. `buffered`
+    /// holds bytes read from the stream after the handshake response.
+    pub fn from_stream(stream: S, buffered: Bytes) -> WebSocket<S> {
+        WebSocket {
+            stream,
+            read_buf: BytesMut::from(&buffered[..]),
+            write_buf: BytesMut::new(),
+            fragments: None,
+            frame_size: None,
+            max_message_size: MAX_MESSAGE_SIZE,
+            protocol: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Checks the `101` response to the handshake request sent with `key` and
+    /// wraps the upgraded stream.
+    pub(crate) fn handshake(
+        stream: BufStream<S>,
+        key: &str,
+        response: &Response,
+    ) -> Result<WebSocket<S>, Error> {
+        if !response
+            .header("Upgrade")
+            .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+        {
+            return Err(Error::WebSocketHandshake("no Upgrade: websocket".into()));
+        }
+        if !response
+            .headers()
+            .get_array("Connection")
+            .iter()
+            .any(|value| value.eq_ignore_ascii_case("upgrade"))
+        {
+            return Err(Error::WebSocketHandshake("no Connection: Upgrade".into()));
+        }
+        if response.header("Sec-WebSocket-Accept") != Some(accept_key(key)) {
+            return Err(Error::WebSocketHandshake(
+                "wrong Sec-WebSocket-Accept".into(),
+            ));
+        }
+        let (stream, buffered) = stream.into_parts();
+        let mut websocket = WebSocket::from_stream(stream, buffered);
+        websocket.protocol = response.header("Sec-WebSocket-Protocol");
+        Ok(websocket)
+    }
+
+    /// Subprotocol selected by the server.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Splits sent data messages into frames of at most `size` bytes.
+    pub fn set_frame_size(&mut self, size: Option<usize>) {
+        self.frame_size = size.filter(|size| *size > 0);
+    }
+
+    /// Largest message accepted from the server, 64 MiB by default.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            let n = ready!(Pin::new(&mut self.stream).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.advance(n);
+        }
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut buf = [0u8; BUFFER_CAPACITY];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        ready!(Pin::new(&mut self.stream).poll_read(cx, &mut read_buf))?;
+        self.read_buf.extend_from_slice(read_buf.filled());
+        Poll::Ready(Ok(read_buf.filled().len()))
+    }
+
+    fn queue(&mut self, message: Message) -> Result<(), Error> {
+        if self.close_sent {
+            return Err(Error::WebSocketClosed);
+        }
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OP_TEXT, Bytes::from(text)),
+            Message::Binary(data) => (OP_BINARY, data),
+            Message::Ping(data) => (OP_PING, data),
+            Message::Pong(data) => (OP_PONG, data),
+            Message::Close(frame) => (OP_CLOSE, close_payload(frame.as_ref())),
+        };
+        if opcode >= OP_CLOSE {
+            if payload.len() > MAX_CONTROL_PAYLOAD {
+                return Err(Error::WebSocketProtocol(
+                    "control frame payload over 125 bytes".into(),
+                ));
+            }
+            encode_frame(&mut self.write_buf, true, opcode, &payload);
+            self.close_sent = opcode == OP_CLOSE;
+            return Ok(());
+        }
+        let size = self.frame_size.unwrap_or(payload.len()).max(1);
+        let mut chunks = payload.chunks(size).peekable();
+        let mut opcode = opcode;
+        if chunks.peek().is_none() {
+            encode_frame(&mut self.write_buf, true, opcode, &[]);
+        }
+        while let Some(chunk) = chunks.next() {
+            encode_frame(&mut self.write_buf, chunks.peek().is_none(), opcode, chunk);
+            opcode = OP_CONTINUATION;
+        }
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Message>, Error> {
+        match frame.opcode {
+            OP_TEXT | OP_BINARY => {
+                if self.fragments.is_some() {
+                    return Err(Error::WebSocketProtocol(
+                        "data frame inside a fragmented message".into(),
+                    ));
+                }
+                if frame.fin {
+                    return data_message(frame.opcode, frame.payload).map(Some);
+                }
+                self.fragments = Some((frame.opcode, BytesMut::from(&frame.payload[..])));
+                Ok(None)
+            }
+            OP_CONTINUATION => {
+                let Some((_, data)) = &mut self.fragments else {
+                    return Err(Error::WebSocketProtocol(
+                        "continuation without a fragmented message".into(),
+                    ));
+                };
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return Err(Error::WebSocketProtocol("message too big".into()));
+                }
+                data.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    return Ok(None);
+                }
+                match self.fragments.take() {
+                    Some((opcode, data)) => data_message(opcode, data.freeze()).map(Some),
+                    None => Ok(None),
+                }
+            }
+            OP_PING => {
+                if !self.close_sent {
+                    encode_frame(&mut self.write_buf, true, OP_PONG, &frame.payload);
+                }
+                Ok(Some(Message::Ping(frame.payload)))
+            }
+            OP_PONG => Ok(Some(Message::Pong(frame.payload))),
+            OP_CLOSE => {
+                let close = parse_close(&frame.payload)?;
+                self.close_received = true;
+                if !self.close_sent {
+                    self.close_sent = true;
+                    let payload = close_payload(close.as_ref());
+                    encode_frame(&mut self.write_buf, true, OP_CLOSE, &payload);
+                }
+                Ok(Some(Message::Close(close)))
+            }
+            opcode => Err(Error::WebSocketProtocol(format!(
+                "reserved opcode {opcode:#x}"
+            ))),
+        }
+    }
+}
+
+impl<S> std::fmt::Debug for WebSocket<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("protocol", &self.protocol)
+            .field("close_sent", &self.close_sent)
+            .field("close_received", &self.close_received)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for WebSocket<S> {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.close_received {
+            // The echoed Close has to reach the server before the stream ends.
+            return match ready!(this.poll_write_buf(cx)) {
+                Ok(()) => Poll::Ready(None),
+                Err(err) => Poll::Ready(Some(Err(err.into()))),
+            };
+        }
+        // Pongs queued by earlier reads go out without waiting for a flush.
+        if let Poll::Ready(Err(err)) = this.poll_write_buf(cx) {
+            return Poll::Ready(Some(Err(err.into())));
+        }
+        loop {
+            match parse_frame(&mut this.read_buf, this.max_message_size) {
+                Ok(Some(frame)) => match this.on_frame(frame) {
+                    Ok(Some(message)) => {
+                        if let Poll::Ready(Err(err)) = this.poll_write_buf(cx) {
+                            return Poll::Ready(Some(Err(err.into())));
+                        }
+                        return Poll::Ready(Some(Ok(message)));
+                    }
+                    Ok(None) => continue,
+                    Err(err) => return Poll::Ready(Some(Err(err))),
+                },
+                Ok(None) => {}
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+            match ready!(this.poll_fill(cx)) {
+                Ok(0) => {
+                    return Poll::Ready(Some(Err(Error::Io(io::ErrorKind::UnexpectedEof.into()))));
+                }
+                Ok(_) => {}
+                Err(err) => return Poll::Ready(Some(Err(err.into()))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<Message> for WebSocket<S> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        if this.write_buf.len() >= BUFFER_CAPACITY {
+            ready!(this.poll_write_buf(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<(), Error> {
+        self.get_mut().queue(message)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_write_buf(cx).map_err(Error::from)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        if !this.close_sent {
+            this.queue(Message::Close(None))?;
+        }
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.stream)
+            .poll_shutdown(cx)
+            .map_err(Error::from)
+    }
+}
+
+/// `Sec-WebSocket-Accept` value expected for a `Sec-WebSocket-Key`.
+pub(crate) fn accept_key(key: &str) -> String {
+    STANDARD.encode(Sha1::digest(format!("{key}{ACCEPT_GUID}").as_bytes()))
+}
+
+pub(crate) fn generate_key() -> String {
+    STANDARD.encode(rand::random::<[u8; 16]>())
+}
+
+/// Writes a masked client frame.
+fn encode_frame(buf: &mut BytesMut, fin: bool, opcode: u8, payload: &[u8]) {
+    buf.put_u8(if fin { 0x80 } else { 0 } | opcode);
+    match payload.len() {
+        len @ 0..=125 => buf.put_u8(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            buf.put_u8(0x80 | 126);
+            buf.put_u16(len as u16);
+        }
+        len => {
+            buf.put_u8(0x80 | 127);
+            buf.put_u64(len as u64);
+        }
+    }
+    let mask = rand::random::<[u8; 4]>();
+    buf.put_slice(&mask);
+    buf.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(idx, byte)| byte ^ mask[idx % 4]),
+    );
+}
+
+/// Takes the next complete server frame from `buf`.
+fn parse_frame(buf: &mut BytesMut, max_size: usize) -> Result<Option<Frame>, Error> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0f;
+    if buf[0] & 0x70 != 0 {
+        return Err(Error::WebSocketProtocol("reserved bits set".into()));
+    }
+    if buf[1] & 0x80 != 0 {
+        return Err(Error::WebSocketProtocol("masked server frame".into()));
+    }
+    let (header_len, len) = match buf[1] & 0x7f {
+        126 if buf.len() >= 4 => (4, u16::from_be_bytes([buf[2], buf[3]]) as u64),
+        127 if buf.len() >= 10 => {
+            let mut len = [0u8; 8];
+            len.copy_from_slice(&buf[2..10]);
+            (10, u64::from_be_bytes(len))
+        }
+        126 | 127 => return Ok(None),
+        len => (2, len as u64),
+    };
+    if opcode >= OP_CLOSE && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+        return Err(Error::WebSocketProtocol("invalid control frame".into()));
+    }
+    if len > max_size as u64 {
+        return Err(Error::WebSocketProtocol("message too big".into()));
+    }
+    let len = len as usize;
+    if buf.len() < header_len + len {
+        buf.reserve(header_len + len - buf.len());
+        return Ok(None);
+    }
+    buf.advance(header_len);
+    let payload = buf.split_to(len).freeze();
+    Ok(Some(Frame {
+        fin,
+        opcode,
+        payload,
+    }))
+}
+
+fn data_message(opcode: u8, payload: Bytes) -> Result<Message, Error> {
+    match opcode {
+        OP_TEXT => Ok(Message::Text(String::from_utf8(payload.to_vec())?)),
+        _ => Ok(Message::Binary(payload)),
+    }
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, Error> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(Error::WebSocketProtocol("close frame of 1 byte".into())),
+        [high, low, reason @ ..] => Ok(Some(CloseFrame {
+            code: u16::from_be_bytes([*high, *low]),
+            reason: String::from_utf8(reason.to_vec())?,
+        })),
+    }
+}
+
+fn close_payload(frame: Option<&CloseFrame>) -> Bytes {
+    let mut payload = BytesMut::new();
+    if let Some(frame) = frame {
+        payload.put_u16(frame.code);
+        payload.put_slice(frame.reason.as_bytes());
+    }
+    payload.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+
+    use super::*;
+    use crate::{
+        Client,
+        testing::{Reply, TestServer},
+    };
+
+    /// Reads a client frame and removes its mask.
+    async fn read_client_frame(server: &mut DuplexStream) -> (u8, Vec<u8>) {
+        let mut head = [0u8; 2];
+        server.read_exact(&mut head).await.unwrap();
+        assert_ne!(head[1] & 0x80, 0, "client frames are masked");
+        let len = match head[1] & 0x7f {
+            126 => server.read_u16().await.unwrap() as usize,
+            127 => server.read_u64().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut mask = [0u8; 4];
+        server.read_exact(&mut mask).await.unwrap();
+        let mut payload = vec![0u8; len];
+        server.read_exact(&mut payload).await.unwrap();
+        for (idx, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[idx % 4];
+        }
+        (head[0], payload)
+    }
+
+    // RFC 6455 section 1.3
+    #[test]
+    fn accept_key_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn masked_fragmented_send() {
+        let (client, mut server) = duplex(1024);
+        let mut websocket = WebSocket::from_stream(client, Bytes::new());
+        websocket.set_frame_size(Some(4));
+        websocket
+            .send(Message::Text("fragmented".into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            read_client_frame(&mut server).await,
+            (0x01, b"frag".to_vec())
+        );
+        assert_eq!(
+            read_client_frame(&mut server).await,
+            (0x00, b"ment".to_vec())
+        );
+        assert_eq!(read_client_frame(&mut server).await, (0x80, b"ed".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn receive_fragments_and_ping() {
+        let (client, mut server) = duplex(1024);
+        let mut websocket = WebSocket::from_stream(client, Bytes::from_static(b"\x01\x03Hel"));
+        // A ping between the fragments of a message.
+        server.write_all(b"\x89\x02hi\x80\x02lo").await.unwrap();
+        assert_eq!(
+            websocket.next().await.unwrap().unwrap(),
+            Message::Ping(Bytes::from_static(b"hi"))
+        );
+        assert_eq!(
+            websocket.next().await.unwrap().unwrap(),
+            Message::Text("Hello".into())
+        );
+        assert_eq!(read_client_frame(&mut server).await, (0x8a, b"hi".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn close_handshake() {
+        let (client, mut server) = duplex(1024);
+        let mut websocket = WebSocket::from_stream(client, Bytes::new());
+        server.write_all(b"\x88\x05\x03\xe8bye").await.unwrap();
+        assert_eq!(
+            websocket.next().await.unwrap().unwrap(),
+            Message::Close(Some(CloseFrame::new(1000, "bye")))
+        );
+        assert!(websocket.next().await.is_none());
+        assert_eq!(
+            read_client_frame(&mut server).await,
+            (0x88, b"\x03\xe8bye".to_vec())
+        );
+        assert_eq!(
+            websocket.send(Message::Text("late".into())).await,
+            Err(Error::WebSocketClosed)
+        );
+    }
+
+    #[tokio::test]
+    async fn protocol_errors() {
+        let (client, mut server) = duplex(1024);
+        let mut websocket = WebSocket::from_stream(client, Bytes::new());
+        server
+            .write_all(b"\x81\x82\x00\x00\x00\x00hi")
+            .await
+            .unwrap();
+        assert!(matches!(
+            websocket.next().await,
+            Some(Err(Error::WebSocketProtocol(_)))
+        ));
+        let (client, _server) = duplex(1024);
+        let mut websocket = WebSocket::from_stream(client, Bytes::from_static(b"\x80\x00"));
+        assert!(matches!(
+            websocket.next().await,
+            Some(Err(Error::WebSocketProtocol(_)))
+        ));
+    }
+
+    /// Answers the handshake with `accept`, or the right key when it is
+    /// `None`, and sends the first message together with the response.
+    async fn serve_handshake(accept: Option<&'static str>) -> TestServer {
+        TestServer::with_handler(move |request| {
+            let key = request.headers.get("Sec-WebSocket-Key").unwrap_or_default();
+            let accept_value = accept.map_or_else(|| accept_key(&key), str::to_string);
+            let mut response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {accept_value}\r\n\r\n"
+            )
+            .into_bytes();
+            response.extend_from_slice(b"\x81\x05hello");
+            Reply::raw(response).echo()
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn client_handshake() {
+        let server = serve_handshake(None).await;
+        let url = format!("ws://{}/chat", server.addr());
+        let mut websocket = Client::builder().get(&url).websocket().await.unwrap();
+        assert_eq!(
+            websocket.next().await.unwrap().unwrap(),
+            Message::Text("hello".into())
+        );
+        websocket.send(Message::Text("hi".into())).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests[0].target, "/chat");
+        assert_eq!(
+            requests[0].headers.get("Upgrade").as_deref(),
+            Some("websocket")
+        );
+        // The echoed frame is masked, which servers must not do.
+        assert!(matches!(
+            websocket.next().await,
+            Some(Err(Error::WebSocketProtocol(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn client_handshake_wrong_accept() {
+        let server = serve_handshake(Some("wrong")).await;
+        let url = format!("ws://{}/chat", server.addr());
+        let err = Client::builder().get(&url).websocket().await.unwrap_err();
+        assert_eq!(
+            err,
+            Error::WebSocketHandshake("wrong Sec-WebSocket-Accept".into())
+        );
+    }
+}
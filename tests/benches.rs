@@ -1,3 +1,112 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use netc::{BufStream, Response};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+const ITERATIONS: usize = 2000;
+
+/// In-memory stream that counts `poll_read` calls, standing in for a socket
+/// or TLS session where every read has a fixed cost.
+struct CountingStream {
+    data: &'static [u8],
+    pos: usize,
+    reads: usize,
+}
+
+impl CountingStream {
+    fn new(data: &'static [u8]) -> Self {
+        CountingStream {
+            data,
+            pos: 0,
+            reads: 0,
+        }
+    }
+}
+
+impl AsyncRead for CountingStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<Result<(), io::Error>> {
+        let this = Pin::get_mut(self);
+        this.reads += 1;
+        let len = (this.data.len() - this.pos).min(buf.remaining());
+        buf.put_slice(&this.data[this.pos..this.pos + len]);
+        this.pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for CountingStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn response() -> &'static [u8] {
+    let mut head = String::from("HTTP/1.1 200 OK\r\n");
+    for i in 0..30 {
+        head.push_str(&format!("X-Header-{i}: some header value number {i}\r\n"));
+    }
+    head.push_str("Content-Length: 1024\r\n\r\n");
+    let mut response = head.into_bytes();
+    response.extend_from_slice(&[b'a'; 1024]);
+    Box::leak(response.into_boxed_slice())
+}
+
+/// The previous approach: one `read_u8` per header byte, re-checking the tail.
+async fn bytewise_response(stream: &mut CountingStream) -> Response {
+    let mut header = Vec::with_capacity(512);
+    while !(header.len() > 4 && header[header.len() - 4..] == b"\r\n\r\n"[..]) {
+        header.push(stream.read_u8().await.unwrap());
+    }
+    let mut response = Response::from_header(&header).unwrap();
+    let mut body = vec![0u8; response.content_len().unwrap()];
+    stream.read_exact(&mut body).await.unwrap();
+    response.body = body.into();
+    response
+}
+
+#[tokio::test]
+async fn bench_response_head_parsing() {
+    let data = response();
+
+    let mut bytewise_reads = 0;
+    for _ in 0..ITERATIONS {
+        let mut stream = CountingStream::new(data);
+        let response = bytewise_response(&mut stream).await;
+        assert_eq!(response.body().len(), 1024);
+        bytewise_reads += stream.reads;
+    }
+
+    let mut buffered_reads = 0;
+    for _ in 0..ITERATIONS {
+        let mut stream = BufStream::new(CountingStream::new(data));
+        let response = stream.get_response().await.unwrap();
+        assert_eq!(response.body().len(), 1024);
+        buffered_reads += stream.get_ref().reads;
+    }
+
+    assert!(buffered_reads * 100 < bytewise_reads);
+}
+
 // #![feature(test)]
 // use netc;
 // use test::Bencher;
@@ -1,5 +1,20 @@
 // use httpmock::prelude::*;
-use netc::Client;
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::{StreamExt, future::BoxFuture};
+use netc::{
+    AuthCache, BandwidthLimiter, Batch, Cache, CacheMode, Cassette, CassetteMode, Client, Error,
+    HarRecorder, Method, MetricsObserver, ProxyPool, RateLimiter, Request, RequestEvent, Strategy,
+    TokenProvider,
+    testing::{HttpProxy, Reply, SocksProxy, TestServer},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
 
 #[tokio::test]
@@ -38,179 +53,1466 @@ async fn test_get_client() {
     assert_eq!(&body, "GET");
 }
 
+/// Mock answering every GET with `body`.
+async fn mock_get(body: &str) -> MockServer {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(body))
+        .mount(&mock_server)
+        .await;
+    mock_server
+}
+
+async fn get_through(url: &str, proxy: &str) -> Result<netc::Response, Error> {
+    Client::builder()
+        .get(url)
+        .proxy(proxy)
+        .build()
+        .await?
+        .send()
+        .await
+}
+
 #[tokio::test]
 async fn test_http_proxy() {
-    let test_var = "TEST_HTTP_PROXY";
-    dotenvy::dotenv().ok();
-    let proxy = match dotenvy::var(test_var) {
-        Ok(it) => it,
-        _ => return,
-    };
+    let proxy = HttpProxy::start().await.unwrap();
+    let mock_server = mock_get("http proxy").await;
+    let response = get_through(&mock_server.uri(), &proxy.url()).await.unwrap();
+    assert!(response.status_code().is_success());
+    assert_eq!(&response.text().unwrap(), "http proxy");
+    assert_eq!(proxy.tunnels(), 1);
+}
+
+#[tokio::test]
+async fn test_http_proxy_auth() {
+    let proxy = HttpProxy::with_auth("test", "tset").await.unwrap();
+    let mock_server = mock_get("http auth proxy").await;
+    let response = get_through(&mock_server.uri(), &proxy.url()).await.unwrap();
+    assert!(response.status_code().is_success());
+    assert_eq!(&response.text().unwrap(), "http auth proxy");
+    let received = &mock_server.received_requests().await.unwrap()[0];
+    assert!(!received.headers.contains_key("proxy-authorization"));
+}
+
+#[tokio::test]
+async fn test_http_proxy_auth_err() {
+    let proxy = HttpProxy::with_auth("test", "tset").await.unwrap();
+    let mock_server = mock_get("http auth proxy").await;
+    let wrong = format!("http://test:wrong@{}", proxy.addr());
+    let response = get_through(&mock_server.uri(), &wrong).await.unwrap();
+    assert_eq!(response.status_code().as_u16(), 407);
+    assert_eq!(proxy.tunnels(), 0);
+}
+
+#[tokio::test]
+async fn test_socks_proxy() {
+    let proxy = SocksProxy::start().await.unwrap();
+    let mock_server = mock_get("socks5 proxy").await;
+    for scheme in ["socks5", "socks5h"] {
+        let response = get_through(&mock_server.uri(), &proxy.url(scheme))
+            .await
+            .unwrap();
+        assert!(response.status_code().is_success());
+        assert_eq!(&response.text().unwrap(), "socks5 proxy");
+    }
+    assert_eq!(proxy.tunnels(), 2);
+}
+
+#[tokio::test]
+async fn test_socks_proxy_100_hits() {
+    let proxy = SocksProxy::start().await.unwrap();
+    let proxy = proxy.url("socks5");
     let mock_server = MockServer::start().await;
     Mock::given(matchers::method("GET"))
-        .respond_with(ResponseTemplate::new(200).set_body_string(test_var))
+        .respond_with(ResponseTemplate::new(200).set_body_string("socks5 proxy"))
+        .up_to_n_times(100)
+        .mount(&mock_server)
+        .await;
+    for _ in 0..100 {
+        let response = get_through(&mock_server.uri(), &proxy).await.unwrap();
+        assert!(response.status_code().is_success());
+        assert_eq!(&response.text().unwrap(), "socks5 proxy");
+    }
+    let response = get_through(&mock_server.uri(), &proxy).await.unwrap();
+    assert_eq!(response.status_code().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn test_socks_proxy_auth() {
+    let proxy = SocksProxy::with_auth("test", "tset").await.unwrap();
+    let mock_server = mock_get("socks5 auth proxy").await;
+    let response = get_through(&mock_server.uri(), &proxy.url("socks5"))
+        .await
+        .unwrap();
+    assert!(response.status_code().is_success());
+    assert_eq!(&response.text().unwrap(), "socks5 auth proxy");
+}
+
+#[tokio::test]
+async fn test_socks_proxy_auth_err() {
+    let proxy = SocksProxy::with_auth("test", "tset").await.unwrap();
+    let mock_server = mock_get("socks5 auth proxy").await;
+    let wrong = format!("socks5://test:wrong@{}", proxy.addr());
+    assert!(get_through(&mock_server.uri(), &wrong).await.is_err());
+    let anonymous = format!("socks5://{}", proxy.addr());
+    assert!(get_through(&mock_server.uri(), &anonymous).await.is_err());
+    assert_eq!(proxy.tunnels(), 0);
+}
+
+#[tokio::test]
+async fn test_connect_proxy() {
+    // Targets other than plain http, like https, go through a CONNECT tunnel.
+    let server = TestServer::start(vec![Reply::new(200).body("tunneled")])
+        .await
+        .unwrap();
+    let proxy = HttpProxy::with_auth("test", "tset").await.unwrap();
+    let target = format!("ws://{}/path", server.addr());
+    let authorized = format!("http://test:tset@{}", proxy.addr());
+    let response = get_through(&target, &authorized).await.unwrap();
+    assert_eq!(&response.text().unwrap(), "tunneled");
+    assert_eq!(server.requests()[0].target, "/path");
+    assert_eq!(proxy.tunnels(), 1);
+    let wrong = format!("http://test:wrong@{}", proxy.addr());
+    assert_eq!(
+        get_through(&target, &wrong).await.err(),
+        Some(Error::ProxyConnect(407))
+    );
+    assert_eq!(proxy.tunnels(), 1);
+}
+
+#[tokio::test]
+async fn test_socks4_proxy() {
+    let proxy = SocksProxy::with_auth("test", "tset").await.unwrap();
+    let mock_server = mock_get("socks4 proxy").await;
+    let response = get_through(&mock_server.uri(), &proxy.url("socks4"))
+        .await
+        .unwrap();
+    assert!(response.status_code().is_success());
+    assert_eq!(&response.text().unwrap(), "socks4 proxy");
+    let wrong = format!("socks4://other@{}", proxy.addr());
+    assert!(get_through(&mock_server.uri(), &wrong).await.is_err());
+}
+
+#[tokio::test]
+async fn test_socks4a_proxy() {
+    let proxy = SocksProxy::start().await.unwrap();
+    let mock_server = mock_get("socks4a proxy").await;
+    // A domain name is resolved by the proxy.
+    let url = format!("http://localhost:{}/", mock_server.address().port());
+    let response = get_through(&url, &proxy.url("socks4a")).await.unwrap();
+    assert!(response.status_code().is_success());
+    assert_eq!(&response.text().unwrap(), "socks4a proxy");
+}
+
+#[tokio::test]
+async fn test_connect_proxy_chain() {
+    let http_proxy = HttpProxy::with_auth("test", "tset").await.unwrap();
+    let socks_proxy = SocksProxy::start().await.unwrap();
+    let mock_server = mock_get("chained").await;
+    let response = Client::builder()
+        .get(&mock_server.uri())
+        .proxy_chain([&http_proxy.url(), &socks_proxy.url("socks5")])
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(&response.text().unwrap(), "chained");
+    assert_eq!((http_proxy.tunnels(), socks_proxy.tunnels()), (1, 1));
+}
+
+#[tokio::test]
+async fn test_test_server_replies() {
+    let server = TestServer::start(vec![
+        Reply::new(200)
+            .header("X-Script", "1")
+            .body("slow pieces")
+            .chunked(4)
+            .pause(Duration::from_millis(20)),
+        Reply::new(200).body("cut short").truncate(42),
+    ])
+    .await
+    .unwrap();
+    let start = std::time::Instant::now();
+    let response = Client::builder()
+        .post(&server.url("/upload"))
+        .body("payload")
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(60));
+    assert_eq!(response.header("X-Script").as_deref(), Some("1"));
+    assert_eq!(&response.text().unwrap(), "slow pieces");
+    let result = Client::builder()
+        .get(&server.url("/"))
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await;
+    assert!(result.is_err());
+    let requests = server.requests();
+    assert_eq!(requests[0].method, Method::Post);
+    assert_eq!(requests[0].target, "/upload");
+    assert_eq!(&requests[0].body[..], b"payload");
+}
+
+#[tokio::test]
+async fn test_proxy_pool_marks_bad_proxy() {
+    let mock_server = MockServer::start().await;
+    let pool = ProxyPool::from_urls(["http://127.0.0.1:1"], Strategy::RoundRobin).unwrap();
+    let client = Client::builder()
+        .get(&mock_server.uri())
+        .proxy_pool(&pool)
+        .build()
+        .await;
+    assert!(client.is_err());
+    let stats = &pool.stats()[0];
+    assert_eq!(stats.failures, 1);
+    assert!(!stats.available);
+    let client = Client::builder()
+        .get(&mock_server.uri())
+        .proxy_pool(&pool)
+        .build()
+        .await;
+    assert_eq!(client.err(), Some(Error::NoProxyAvailable));
+}
+
+#[tokio::test]
+async fn test_proxy_pool_latency() {
+    let server = TestServer::start(vec![
+        Reply::new(200)
+            .delay(Duration::from_millis(300))
+            .body("slow"),
+    ])
+    .await
+    .unwrap();
+    let proxy = HttpProxy::start().await.unwrap();
+    let pool = ProxyPool::from_urls([&proxy.url()], Strategy::RoundRobin).unwrap();
+    let mut client = Client::builder()
+        .get(&server.url("/"))
+        .proxy_pool(&pool)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(client.send().await.unwrap().text().unwrap(), "slow");
+    let stats = &pool.stats()[0];
+    assert_eq!(stats.successes, 1);
+    assert!(stats.latency.unwrap() < Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn test_digest_auth() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::header_regex(
+            "Authorization",
+            r#"^Digest username="user", realm="netc", nonce="abc".*qop=auth, nc=0000000"#,
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string("DIGEST"))
+        .mount(&mock_server)
+        .await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(ResponseTemplate::new(401).insert_header(
+            "WWW-Authenticate",
+            r#"Digest realm="netc", qop="auth", nonce="abc", algorithm=SHA-256"#,
+        ))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let cache = AuthCache::new();
+    for _ in 0..2 {
+        let mut client = Client::builder()
+            .get(&mock_server.uri())
+            .digest_auth("user", "pass")
+            .auth_cache(&cache)
+            .build()
+            .await
+            .unwrap();
+        let response = client.send().await.unwrap();
+        assert_eq!(response.status_code().as_u16(), 200);
+        assert_eq!(&response.text().unwrap(), "DIGEST");
+    }
+}
+
+#[tokio::test]
+async fn test_digest_auth_without_credentials() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(
+            ResponseTemplate::new(401)
+                .insert_header("WWW-Authenticate", r#"Digest realm="netc", nonce="abc""#),
+        )
+        .expect(1)
         .mount(&mock_server)
         .await;
     let mut client = Client::builder()
         .get(&mock_server.uri())
-        .proxy(&proxy)
         .build()
         .await
         .unwrap();
     let response = client.send().await.unwrap();
-    assert!(response.status_code().is_success());
-    assert_eq!(&response.text().unwrap(), test_var);
+    assert_eq!(response.status_code().as_u16(), 401);
+}
+
+struct RefreshingToken {
+    refreshes: AtomicUsize,
+}
+
+impl TokenProvider for RefreshingToken {
+    fn token(&self) -> BoxFuture<'_, Result<String, Error>> {
+        let token = match self.refreshes.load(Ordering::SeqCst) {
+            0 => "stale",
+            _ => "fresh",
+        };
+        Box::pin(async move { Ok(token.to_string()) })
+    }
+
+    fn refresh(&self) -> BoxFuture<'_, Result<(), Error>> {
+        self.refreshes.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async { Ok(()) })
+    }
 }
 
 #[tokio::test]
-async fn test_http_proxy_auth() {
-    let test_var = "TEST_HTTP_AUTH_PROXY";
-    dotenvy::dotenv().ok();
-    let proxy = match dotenvy::var(test_var) {
-        Ok(it) => it,
-        _ => return,
-    };
+async fn test_bearer_token_refresh() {
     let mock_server = MockServer::start().await;
+    Mock::given(matchers::header("Authorization", "Bearer fresh"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("BEARER"))
+        .mount(&mock_server)
+        .await;
     Mock::given(matchers::method("GET"))
-        .respond_with(ResponseTemplate::new(200).set_body_string(test_var))
+        .respond_with(ResponseTemplate::new(401).insert_header(
+            "WWW-Authenticate",
+            r#"Bearer realm="netc", error="invalid_token""#,
+        ))
         .mount(&mock_server)
         .await;
+    let provider = Arc::new(RefreshingToken {
+        refreshes: AtomicUsize::new(0),
+    });
     let mut client = Client::builder()
         .get(&mock_server.uri())
-        .proxy(&proxy)
+        .token_provider(provider.clone())
         .build()
         .await
         .unwrap();
     let response = client.send().await.unwrap();
-    assert!(response.status_code().is_success());
-    assert_eq!(&response.text().unwrap(), test_var);
+    assert_eq!(response.status_code().as_u16(), 200);
+    assert_eq!(&response.text().unwrap(), "BEARER");
+    assert_eq!(provider.refreshes.load(Ordering::SeqCst), 1);
 }
 
 #[tokio::test]
-async fn test_http_proxy_auth_err() {
-    let test_var = "TEST_HTTP_AUTH_ERR_PROXY";
-    dotenvy::dotenv().ok();
-    let proxy = match dotenvy::var(test_var) {
-        Ok(it) => it,
-        _ => return,
-    };
+async fn test_bearer_token_cross_origin_redirect() {
+    let other_server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("OTHER"))
+        .mount(&other_server)
+        .await;
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::header("Authorization", "Bearer stale"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", other_server.uri()))
+        .mount(&mock_server)
+        .await;
+    let provider = Arc::new(RefreshingToken {
+        refreshes: AtomicUsize::new(0),
+    });
+    let response = Client::builder()
+        .get(&mock_server.uri())
+        .token_provider(provider)
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(&response.text().unwrap(), "OTHER");
+    let received = other_server.received_requests().await.unwrap();
+    assert_eq!(received.len(), 1);
+    assert!(!received[0].headers.contains_key("authorization"));
+}
+
+#[tokio::test]
+async fn test_bearer_auth() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::header("Authorization", "Bearer token"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+    let mut client = Client::builder()
+        .get(&mock_server.uri())
+        .bearer_auth("token")
+        .build()
+        .await
+        .unwrap();
+    let response = client.send().await.unwrap();
+    assert_eq!(response.status_code().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn test_netrc() {
+    let target = MockServer::start().await;
+    Mock::given(matchers::header_exists("Authorization"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&target)
+        .await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&target)
+        .await;
     let mock_server = MockServer::start().await;
+    // Basic user:pass
+    Mock::given(matchers::header("Authorization", "Basic dXNlcjpwYXNz"))
+        .respond_with(ResponseTemplate::new(302).insert_header(
+            "Location",
+            format!("http://localhost:{}/", target.address().port()).as_str(),
+        ))
+        .mount(&mock_server)
+        .await;
+    let netrc = std::env::temp_dir().join(format!("netrc-{}", std::process::id()));
+    std::fs::write(&netrc, "machine 127.0.0.1 login user password pass\n").unwrap();
+    let mut client = Client::builder()
+        .get(&mock_server.uri())
+        .netrc(&netrc)
+        .build()
+        .await
+        .unwrap();
+    let response = client.send().await.unwrap();
+    std::fs::remove_file(&netrc).unwrap();
+    assert_eq!(response.status_code().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn test_netrc_default_cross_origin() {
+    let target = MockServer::start().await;
+    Mock::given(matchers::header_exists("Authorization"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&target)
+        .await;
     Mock::given(matchers::method("GET"))
-        .respond_with(ResponseTemplate::new(200).set_body_string(test_var))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&target)
+        .await;
+    let mock_server = MockServer::start().await;
+    // Basic user:pass
+    Mock::given(matchers::header("Authorization", "Basic dXNlcjpwYXNz"))
+        .respond_with(ResponseTemplate::new(302).insert_header(
+            "Location",
+            format!("http://localhost:{}/", target.address().port()).as_str(),
+        ))
         .mount(&mock_server)
         .await;
+    let netrc = std::env::temp_dir().join(format!("netrc-default-{}", std::process::id()));
+    std::fs::write(&netrc, "default login user password pass\n").unwrap();
     let mut client = Client::builder()
         .get(&mock_server.uri())
-        .proxy(&proxy)
+        .netrc(&netrc)
         .build()
         .await
         .unwrap();
     let response = client.send().await.unwrap();
-    assert_eq!(response.status_code().as_u16(), 407);
+    std::fs::remove_file(&netrc).unwrap();
+    assert_eq!(response.status_code().as_u16(), 200);
 }
 
 #[tokio::test]
-async fn test_socks_proxy() {
-    let test_var = "TEST_SOCKS5_PROXY";
-    dotenvy::dotenv().ok();
-    let proxy = match dotenvy::var(test_var) {
-        Ok(it) => it,
-        _ => return,
-    };
+async fn test_netrc_missing_file() {
+    let netrc = std::env::temp_dir().join(format!("netrc-missing-{}", std::process::id()));
+    let client = Client::builder()
+        .get("http://127.0.0.1:1/")
+        .netrc(&netrc)
+        .build()
+        .await;
+    assert!(matches!(client, Err(Error::Netrc(reason)) if reason.contains("netrc-missing")));
+}
+
+#[tokio::test]
+async fn test_redirect_drops_authorization() {
+    let target = MockServer::start().await;
+    Mock::given(matchers::header_exists("Authorization"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&target)
+        .await;
+    Mock::given(matchers::header(
+        "Host",
+        format!("localhost:{}", target.address().port()),
+    ))
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&target)
+    .await;
     let mock_server = MockServer::start().await;
     Mock::given(matchers::method("GET"))
-        .respond_with(ResponseTemplate::new(200).set_body_string(test_var))
+        .respond_with(ResponseTemplate::new(302).insert_header(
+            "Location",
+            format!("http://localhost:{}/", target.address().port()).as_str(),
+        ))
         .mount(&mock_server)
         .await;
     let mut client = Client::builder()
         .get(&mock_server.uri())
-        .proxy(&proxy)
+        .bearer_auth("secret")
         .build()
         .await
         .unwrap();
     let response = client.send().await.unwrap();
-    assert!(response.status_code().is_success());
-    assert_eq!(&response.text().unwrap(), test_var);
+    assert_eq!(response.status_code().as_u16(), 200);
 }
 
 #[tokio::test]
-async fn test_socks_proxy_100_hits() {
-    let test_var = "TEST_SOCKS5_PROXY";
-    dotenvy::dotenv().ok();
-    let proxy = match dotenvy::var(test_var) {
-        Ok(it) => it,
-        _ => return,
-    };
+async fn test_upgrade() {
+    let server = TestServer::start(vec![
+        Reply::raw("HTTP/1.1 101 Switching Protocols\r\nUpgrade: echo\r\n\r\nearly").echo(),
+    ])
+    .await
+    .unwrap();
+    let client = Client::builder()
+        .get(&server.url("/"))
+        .upgrade("echo")
+        .build()
+        .await
+        .unwrap();
+    let (response, mut stream) = client.upgrade().await.unwrap();
+    assert_eq!(response.header("Upgrade"), Some("echo".to_string()));
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"early");
+    stream.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    let requests = server.requests();
+    assert_eq!(requests[0].headers.get("Upgrade").as_deref(), Some("echo"));
+}
+
+#[tokio::test]
+async fn test_upgrade_refused() {
     let mock_server = MockServer::start().await;
     Mock::given(matchers::method("GET"))
-        .respond_with(ResponseTemplate::new(200).set_body_string(test_var))
-        .up_to_n_times(100)
+        .respond_with(ResponseTemplate::new(200))
         .mount(&mock_server)
         .await;
-    for _ in 0..100 {
+    let client = Client::builder()
+        .get(&mock_server.uri())
+        .upgrade("echo")
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(
+        client.upgrade().await.unwrap_err(),
+        Error::UpgradeRefused(200)
+    );
+}
+
+#[tokio::test]
+async fn test_connect_takeover() {
+    let server = TestServer::start(vec![Reply::new(200).body("tunnel")])
+        .await
+        .unwrap();
+    let proxy = HttpProxy::with_auth("user", "pass").await.unwrap();
+    let client = Client::builder()
+        .method("CONNECT")
+        .url(&format!("https://{}", server.addr()))
+        .proxy(&proxy.url())
+        .build()
+        .await
+        .unwrap();
+    let (_, mut stream) = client.upgrade().await.unwrap();
+    stream
+        .write_all(b"GET /tunneled HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.unwrap();
+    assert!(buf.ends_with(b"\r\n\r\ntunnel"));
+    assert_eq!(proxy.tunnels(), 1);
+    assert_eq!(server.requests()[0].target, "/tunneled");
+}
+
+#[tokio::test]
+async fn test_event_source_reconnects() {
+    let server = TestServer::start(vec![
+        Reply::raw(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n\
+             retry: 10\nid: 1\ndata: one\n\ndata: lost",
+        ),
+        Reply::new(200)
+            .header("Content-Type", "text/event-stream; charset=utf-8")
+            .body("event: two\ndata: 2\n\n")
+            .chunked(18),
+        Reply::new(204),
+    ])
+    .await
+    .unwrap();
+    let mut events = Client::builder().get(&server.url("/events")).event_source();
+    let event = events.next().await.unwrap().unwrap();
+    assert_eq!(
+        (event.event.as_str(), event.data.as_str()),
+        ("message", "one")
+    );
+    let event = events.next().await.unwrap().unwrap();
+    assert_eq!((event.event.as_str(), event.data.as_str()), ("two", "2"));
+    assert_eq!(event.id.as_deref(), Some("1"));
+    assert!(events.next().await.is_none());
+    assert_eq!(events.retry(), Some(Duration::from_millis(10)));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(
+        requests[0].headers.get("Accept").as_deref(),
+        Some("text/event-stream")
+    );
+    assert_eq!(requests[0].headers.get("Last-Event-ID"), None);
+    assert_eq!(
+        requests[1].headers.get("Last-Event-ID").as_deref(),
+        Some("1")
+    );
+}
+
+#[tokio::test]
+async fn test_event_source_wrong_type() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("data: x\n\n"))
+        .mount(&mock_server)
+        .await;
+    let mut events = Client::builder().get(&mock_server.uri()).event_source();
+    assert!(matches!(
+        events.next().await,
+        Some(Err(Error::EventSourceType(_)))
+    ));
+    assert!(events.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_event_source_redirect() {
+    let server = TestServer::start(vec![
+        Reply::new(302).header("Location", "/moved"),
+        Reply::new(200)
+            .header("Content-Type", "text/event-stream")
+            .body("data: moved\n\n"),
+    ])
+    .await
+    .unwrap();
+    let mut events = Client::builder().get(&server.url("/events")).event_source();
+    let event = events.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "moved");
+    let requests = server.requests();
+    assert_eq!(requests[1].target, "/moved");
+    assert_eq!(
+        requests[1].headers.get("Accept").as_deref(),
+        Some("text/event-stream")
+    );
+}
+
+#[tokio::test]
+async fn test_event_source_line_limit() {
+    let server = TestServer::start(vec![
+        Reply::new(200)
+            .header("Content-Type", "text/event-stream")
+            .chunked(64 * 1024)
+            .body(vec![b'a'; 2 << 20]),
+    ])
+    .await
+    .unwrap();
+    let mut events = Client::builder().get(&server.url("/")).event_source();
+    assert_eq!(
+        events.next().await.unwrap(),
+        Err(Error::EventSourceLine(1 << 20))
+    );
+    assert!(events.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_download_resumes() {
+    let server = TestServer::start(vec![
+        Reply::raw(&b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nETag: \"v1\"\r\n\r\n0123"[..]),
+        Reply::new(206)
+            .header("Content-Range", "bytes 4-9/10")
+            .body("456789"),
+    ])
+    .await
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("netc-resume-{}", std::process::id()));
+    let last = Arc::new(std::sync::Mutex::new(None));
+    let progress = last.clone();
+    let size = Client::builder()
+        .get(&server.url("/dump"))
+        .download_to(&path)
+        .on_progress(move |p| *progress.lock().unwrap() = Some(p))
+        .await
+        .unwrap();
+    let content = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(content, b"0123456789");
+    let last = last.lock().unwrap().unwrap();
+    assert_eq!((last.transferred, last.total), (10, Some(10)));
+    let requests = server.requests();
+    assert_eq!(requests[0].headers.get("Range"), None);
+    assert_eq!(
+        requests[1].headers.get("Range").as_deref(),
+        Some("bytes=4-")
+    );
+    assert_eq!(
+        requests[1].headers.get("If-Range").as_deref(),
+        Some("\"v1\"")
+    );
+}
+
+#[tokio::test]
+async fn test_download_resumes_later() {
+    let server = TestServer::start(vec![
+        Reply::raw(&b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nETag: \"v1\"\r\n\r\n0123"[..]),
+        Reply::raw(
+            &b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 4-9/10\r\n\
+               Content-Length: 6\r\n\r\n45"[..],
+        ),
+        Reply::new(206)
+            .header("Content-Range", "bytes 6-9/10")
+            .body("6789"),
+    ])
+    .await
+    .unwrap();
+    let name = format!("netc-resume-later-{}", std::process::id());
+    let path = std::env::temp_dir().join(&name);
+    let partial = std::env::temp_dir().join(format!("{name}.partial"));
+    let validator = std::env::temp_dir().join(format!("{name}.partial.validator"));
+    let start = std::time::Instant::now();
+    let result = Client::builder()
+        .get(&server.url("/dump"))
+        .download_to(&path)
+        .max_retries(1)
+        .retry_delay(Duration::from_millis(100))
+        .await;
+    assert!(result.is_err());
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(std::fs::read(&partial).unwrap(), b"012345");
+    assert_eq!(std::fs::read_to_string(&validator).unwrap(), "\"v1\"");
+    let size = Client::builder()
+        .get(&server.url("/dump"))
+        .download_to(&path)
+        .await
+        .unwrap();
+    let content = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(content, b"0123456789");
+    assert!(!partial.exists() && !validator.exists());
+    let requests = server.requests();
+    assert_eq!(
+        requests[2].headers.get("Range").as_deref(),
+        Some("bytes=6-")
+    );
+    assert_eq!(
+        requests[2].headers.get("If-Range").as_deref(),
+        Some("\"v1\"")
+    );
+}
+
+#[tokio::test]
+async fn test_download_range_ignored() {
+    let server = TestServer::start(vec![
+        Reply::raw("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123"),
+        Reply::new(200).body("abcdefghij"),
+    ])
+    .await
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("netc-ignored-{}", std::process::id()));
+    let size = Client::builder()
+        .get(&server.url("/dump"))
+        .download_to(&path)
+        .await
+        .unwrap();
+    let content = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(content, b"abcdefghij");
+}
+
+/// Serves `BODY` on every connection, honoring `Range` when `ranges` is set.
+async fn serve_ranges(ranges: bool) -> TestServer {
+    const BODY: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+    TestServer::with_handler(move |request| {
+        let range = request.headers.get("Range").and_then(|range| {
+            let (first, last) = range.strip_prefix("bytes=")?.split_once('-')?;
+            let first: usize = first.parse().ok()?;
+            Some((first, last.parse().unwrap_or(BODY.len() - 1)))
+        });
+        match range {
+            Some((first, last)) if ranges => Reply::new(206)
+                .header("Accept-Ranges", "bytes")
+                .header(
+                    "Content-Range",
+                    &format!("bytes {first}-{last}/{}", BODY.len()),
+                )
+                .body(&BODY[first..=last]),
+            _ => Reply::new(200).body(BODY),
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_download_segments() {
+    let server = serve_ranges(true).await;
+    let path = std::env::temp_dir().join(format!("netc-segments-{}", std::process::id()));
+    let last = Arc::new(AtomicUsize::new(0));
+    let progress = last.clone();
+    let size = Client::builder()
+        .get(&server.url("/dump"))
+        .download_to(&path)
+        .segments(3)
+        .on_progress(move |p| {
+            progress.fetch_max(p.transferred as usize, Ordering::SeqCst);
+        })
+        .await
+        .unwrap();
+    let content = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(size, 26);
+    assert_eq!(content, b"abcdefghijklmnopqrstuvwxyz");
+    assert_eq!(last.load(Ordering::SeqCst), 26);
+    let mut ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.headers.get("Range"))
+        .collect();
+    ranges.sort();
+    assert_eq!(
+        ranges,
+        ["bytes=0-0", "bytes=0-8", "bytes=18-25", "bytes=9-17"]
+    );
+}
+
+#[tokio::test]
+async fn test_download_segments_fallback() {
+    let server = serve_ranges(false).await;
+    let path = std::env::temp_dir().join(format!("netc-fallback-{}", std::process::id()));
+    let size = Client::builder()
+        .get(&server.url("/dump"))
+        .download_to(&path)
+        .segments(4)
+        .await
+        .unwrap();
+    let content = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(size, 26);
+    assert_eq!(content, b"abcdefghijklmnopqrstuvwxyz");
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].headers.get("Range"), None);
+}
+
+#[tokio::test]
+async fn test_download_probe_fails() {
+    let server = TestServer::start(vec![Reply::raw(""), Reply::new(200).body("abcdef")])
+        .await
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("netc-probe-fails-{}", std::process::id()));
+    let size = Client::builder()
+        .get(&server.url("/dump"))
+        .download_to(&path)
+        .segments(4)
+        .await
+        .unwrap();
+    let content = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(size, 6);
+    assert_eq!(content, b"abcdef");
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[0].headers.get("Range").as_deref(),
+        Some("bytes=0-0")
+    );
+    assert_eq!(requests[1].headers.get("Range"), None);
+}
+
+#[tokio::test]
+async fn test_progress_callbacks() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![b'r'; 50000]))
+        .mount(&mock_server)
+        .await;
+    let uploads = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let downloads = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let (up, down) = (uploads.clone(), downloads.clone());
+    let response = Client::builder()
+        .post(&mock_server.uri())
+        .body(vec![b'u'; 40000])
+        .on_upload_progress(move |p| up.lock().unwrap().push((p.transferred, p.total)))
+        .on_download_progress(move |p| down.lock().unwrap().push((p.transferred, p.total)))
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.body().len(), 50000);
+    let uploads = uploads.lock().unwrap();
+    assert_eq!(uploads.len(), 3);
+    assert_eq!(uploads.last(), Some(&(40000, Some(40000))));
+    let downloads = downloads.lock().unwrap();
+    assert!(downloads.len() > 1);
+    assert!(downloads.windows(2).all(|w| w[0].0 <= w[1].0));
+    assert_eq!(downloads.last(), Some(&(50000, Some(50000))));
+}
+
+#[tokio::test]
+async fn test_streaming_progress() {
+    let server = TestServer::start(vec![
+        Reply::new(200)
+            .body(vec![b'r'; 50000])
+            .pause(Duration::from_millis(10)),
+        Reply::new(200).body(vec![b'c'; 30000]).chunked(10000),
+    ])
+    .await
+    .unwrap();
+    for (len, total) in [(50000, Some(50000)), (30000, None)] {
+        let downloads = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let down = downloads.clone();
+        let (_, body) = Client::builder()
+            .get(&server.url("/"))
+            .on_download_progress(move |p| down.lock().unwrap().push((p.transferred, p.total)))
+            .build()
+            .await
+            .unwrap()
+            .send_streaming()
+            .await
+            .unwrap();
+        assert_eq!(body.bytes().await.unwrap().len(), len);
+        let downloads = downloads.lock().unwrap();
+        assert!(downloads.len() > 1);
+        assert!(downloads.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(downloads.last(), Some(&(len as u64, total)));
+    }
+}
+
+#[tokio::test]
+async fn test_rate_limiter() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+    let limiter = RateLimiter::new(10.0).burst(2);
+    let start = std::time::Instant::now();
+    for _ in 0..4 {
+        let builder = Client::builder()
+            .get(&mock_server.uri())
+            .rate_limiter(&limiter);
+        builder.build().await.unwrap().send().await.unwrap();
+    }
+    // Two requests in the burst, then one every 100ms.
+    assert!(start.elapsed() >= Duration::from_millis(190));
+}
+
+#[tokio::test]
+async fn test_download_limit() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![b'x'; 20_000]))
+        .mount(&mock_server)
+        .await;
+    let limiter = BandwidthLimiter::new(20_000);
+    let start = std::time::Instant::now();
+    for _ in 0..2 {
+        let response = Client::builder()
+            .get(&mock_server.uri())
+            .download_limit(&limiter)
+            .build()
+            .await
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.body().len(), 20_000);
+    }
+    // 40kB at 20kB/s with a 20kB burst. The second connection waits for the
+    // budget its first read overdrew.
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+/// Answers every connection with its request path after `delay`.
+async fn serve_slowly(delay: Duration) -> TestServer {
+    TestServer::with_handler(move |request| {
+        Reply::new(200).body(request.target.clone()).delay(delay)
+    })
+    .await
+    .unwrap()
+}
+
+fn batch_requests(base: &str, count: usize) -> Vec<Request> {
+    (0..count)
+        .map(|i| Request::new(Method::Get, &format!("{base}/{i}").parse().unwrap()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_batch_ordered() {
+    let server = serve_slowly(Duration::from_millis(20)).await;
+    let results: Vec<_> = Batch::new()
+        .limit(10)
+        .per_host(3)
+        .ordered(true)
+        .run(batch_requests(&server.url(""), 12))
+        .collect()
+        .await;
+    assert_eq!(results.len(), 12);
+    for (i, (index, result)) in results.into_iter().enumerate() {
+        assert_eq!(index, i);
+        assert_eq!(result.unwrap().body(), format!("/{i}").as_bytes());
+    }
+    assert_eq!(server.peak_connections(), 3);
+}
+
+#[tokio::test]
+async fn test_batch_as_completed() {
+    let server = serve_slowly(Duration::from_millis(20)).await;
+    let mut requests = batch_requests(&server.url(""), 8);
+    requests.push(Request::new(
+        Method::Get,
+        &"http://127.0.0.1:1/".parse().unwrap(),
+    ));
+    let mut indexes = Vec::new();
+    let mut fetches = Batch::new().limit(2).per_host(4).run(requests);
+    while let Some((index, result)) = fetches.next().await {
+        assert_eq!(result.is_err(), index == 8);
+        indexes.push(index);
+    }
+    indexes.sort();
+    assert_eq!(indexes, (0..9).collect::<Vec<_>>());
+    assert!(server.peak_connections() <= 2);
+}
+
+#[tokio::test]
+async fn test_batch_cancel() {
+    let server = serve_slowly(Duration::from_secs(60)).await;
+    let mut fetches = Batch::new().run(batch_requests(&server.url(""), 4));
+    let cancel = fetches.cancel_handle();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        cancel.abort();
+    });
+    let next = tokio::time::timeout(Duration::from_secs(5), fetches.next()).await;
+    assert!(matches!(next, Ok(None)));
+}
+
+#[tokio::test]
+async fn test_cache_fresh() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Cache-Control", "max-age=60")
+                .set_body_string("cached"),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let cache = Cache::memory();
+    for hit in 0..3 {
         let mut client = Client::builder()
             .get(&mock_server.uri())
-            .proxy(&proxy)
+            .cache(&cache)
             .build()
             .await
             .unwrap();
         let response = client.send().await.unwrap();
-        assert!(response.status_code().is_success());
-        assert_eq!(&response.text().unwrap(), test_var);
+        assert_eq!(response.text().unwrap(), "cached");
+        assert_eq!(response.timings().remote_addr.is_some(), hit == 0);
+    }
+}
+
+#[tokio::test]
+async fn test_cache_revalidate() {
+    let server = TestServer::start(vec![
+        Reply::new(200)
+            .header("Cache-Control", "no-cache")
+            .header("ETag", "\"v1\"")
+            .body("hello"),
+        Reply::new(304)
+            .header("ETag", "\"v1\"")
+            .header("X-Checked", "yes"),
+    ])
+    .await
+    .unwrap();
+    let cache = Cache::memory();
+    let mut responses = Vec::new();
+    for _ in 0..2 {
+        let mut client = Client::builder()
+            .get(&server.url("/"))
+            .cache(&cache)
+            .build()
+            .await
+            .unwrap();
+        responses.push(client.send().await.unwrap());
     }
+    let requests = server.requests();
+    assert_eq!(requests[0].headers.get("If-None-Match"), None);
+    assert_eq!(
+        requests[1].headers.get("If-None-Match").as_deref(),
+        Some("\"v1\"")
+    );
+    assert_eq!(responses[1].status_code().as_u16(), 200);
+    assert_eq!(responses[1].text().unwrap(), "hello");
+    assert_eq!(responses[1].header("X-Checked").as_deref(), Some("yes"));
+}
+
+#[tokio::test]
+async fn test_cache_caller_validators() {
+    let server = TestServer::start(vec![
+        Reply::new(200)
+            .header("Cache-Control", "no-cache")
+            .header("ETag", "\"v1\"")
+            .body("hello"),
+        Reply::new(304).header("ETag", "\"v0\""),
+    ])
+    .await
+    .unwrap();
+    let cache = Cache::memory();
     let mut client = Client::builder()
-        .get(&mock_server.uri())
-        .proxy(&proxy)
+        .get(&server.url("/"))
+        .cache(&cache)
+        .build()
+        .await
+        .unwrap();
+    client.send().await.unwrap();
+    let mut client = Client::builder()
+        .get(&server.url("/"))
+        .header("If-None-Match", "\"v0\"")
+        .cache(&cache)
         .build()
         .await
         .unwrap();
     let response = client.send().await.unwrap();
-    assert_eq!(response.status_code().as_u16(), 404);
+    assert_eq!(response.status_code().as_u16(), 304);
+    assert_eq!(
+        client
+            .request()
+            .get_headers()
+            .get("If-None-Match")
+            .as_deref(),
+        Some("\"v0\"")
+    );
+    let requests = server.requests();
+    assert_eq!(
+        requests[1].headers.get("If-None-Match").as_deref(),
+        Some("\"v0\"")
+    );
 }
 
 #[tokio::test]
-async fn test_socks_proxy_auth() {
-    let test_var = "TEST_SOCKS5_AUTH_PROXY";
-    dotenvy::dotenv().ok();
-    let proxy = match dotenvy::var(test_var) {
-        Ok(it) => it,
-        _ => return,
-    };
+async fn test_cache_modes() {
     let mock_server = MockServer::start().await;
     Mock::given(matchers::method("GET"))
-        .respond_with(ResponseTemplate::new(200).set_body_string(test_var))
+        .respond_with(ResponseTemplate::new(200).insert_header("Cache-Control", "max-age=0"))
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+    let dir = std::env::temp_dir().join(format!("netc-cache-{}", std::process::id()));
+    let cache = Cache::disk(&dir).unwrap();
+    let send = |mode| {
+        let builder = Client::builder()
+            .get(&mock_server.uri())
+            .cache(&cache)
+            .cache_mode(mode);
+        async move { builder.build().await?.send().await }
+    };
+    assert_eq!(send(CacheMode::OnlyIfCached).await, Err(Error::NotCached));
+    send(CacheMode::NoStore).await.unwrap();
+    assert_eq!(send(CacheMode::OnlyIfCached).await, Err(Error::NotCached));
+    send(CacheMode::Default).await.unwrap();
+    // Stale, but forced from another cache over the same directory.
+    let reopened = Cache::disk(&dir).unwrap();
+    let response = Client::builder()
+        .get(&mock_server.uri())
+        .cache(&reopened)
+        .cache_mode(CacheMode::ForceCache)
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(response.header("Age").is_some());
+}
+
+#[tokio::test]
+async fn test_har_redirects() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::path("/old"))
+        .respond_with(ResponseTemplate::new(301).insert_header("Location", "/new"))
+        .mount(&mock_server)
+        .await;
+    Mock::given(matchers::path("/new"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("moved \"here\""))
+        .mount(&mock_server)
+        .await;
+    let recorder = HarRecorder::new();
+    let response = Client::builder()
+        .get(&format!("{}/old?a=1", mock_server.uri()))
+        .har(&recorder)
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().unwrap(), "moved \"here\"");
+    let entries = recorder.entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].request.url().path(), "/old");
+    assert_eq!(entries[1].request.url().path(), "/new");
+    assert!(!entries[0].timings.reused);
+    assert_eq!(entries[1].timings.remote_addr, Some(*mock_server.address()));
+    let json = recorder.to_json();
+    assert!(json.starts_with(r#"{"log":{"version":"1.2","creator":{"name":"netc""#));
+    assert!(json.contains(r#""queryString":[{"name":"a","value":"1"}]"#));
+    assert!(json.contains(r#""status":301"#));
+    assert!(json.contains(r#""redirectURL":"/new""#));
+    assert!(json.contains(r#""text":"moved \"here\"""#));
+    assert!(json.contains(r#""serverIPAddress":"127.0.0.1""#));
+    let path = std::env::temp_dir().join(format!("netc-{}.har", std::process::id()));
+    recorder.save(&path).await.unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(saved, json);
+}
+
+#[tokio::test]
+async fn test_har_connect_failure() {
+    let url = "http://127.0.0.1:1/";
+    let recorder = HarRecorder::new();
+    let result = Client::builder().get(url).har(&recorder).build().await;
+    assert!(result.is_err());
+    let entries = recorder.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].request.url().as_str(), url);
+    assert!(entries[0].response.is_err());
+    assert!(recorder.to_json().contains(r#""_error":"#));
+}
+
+#[tokio::test]
+async fn test_cassette_replay() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::path("/data"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("recorded"))
+        .expect(1)
         .mount(&mock_server)
         .await;
+    let path = std::env::temp_dir().join(format!("netc-cassette-{}", std::process::id()));
+    let send = |cassette: Cassette, path: &str| {
+        let builder = Client::builder()
+            .get(&format!("{}{path}", mock_server.uri()))
+            .header("Authorization", "Bearer secret")
+            .cassette(&cassette);
+        async move { builder.build().await?.send().await }
+    };
+    let cassette = Cassette::open(&path).unwrap();
+    let response = send(cassette.clone(), "/data").await.unwrap();
+    assert_eq!(response.text().unwrap(), "recorded");
+    let response = send(cassette.clone(), "/data").await.unwrap();
+    assert_eq!(response.text().unwrap(), "recorded");
+    assert_eq!(cassette.len(), 1);
+    let saved = String::from_utf8(std::fs::read(&path).unwrap()).unwrap();
+    assert!(!saved.contains("secret"));
+    assert!(saved.contains("authorization: REDACTED"));
+
+    let replay = Cassette::open(&path).unwrap().mode(CassetteMode::Replay);
+    let response = send(replay.clone(), "/data").await.unwrap();
+    assert_eq!(response.text().unwrap(), "recorded");
+    let missing = send(replay, "/other").await;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        missing,
+        Err(Error::NotRecorded(format!(
+            "GET {}/other",
+            mock_server.uri()
+        )))
+    );
+}
+
+#[tokio::test]
+async fn test_response_timings() {
+    let proxy = SocksProxy::start().await.unwrap();
+    let mock_server = mock_get("timed").await;
     let mut client = Client::builder()
         .get(&mock_server.uri())
-        .proxy(&proxy)
+        .header("Connection", "keep-alive")
+        .proxy(&proxy.url("socks5"))
         .build()
         .await
         .unwrap();
-    let response = client.send().await.unwrap();
+    let first = client.send().await.unwrap();
+    let timings = first.timings();
+    assert!(!timings.reused);
+    assert_eq!(timings.remote_addr, Some(proxy.addr()));
+    assert!(timings.local_addr.is_some());
+    assert!(!timings.connect.is_zero() && !timings.proxy.is_zero());
+    assert!(timings.tls.is_zero());
+    assert!(timings.first_byte >= timings.send);
+    assert!(timings.total >= timings.setup() + timings.first_byte + timings.transfer);
+    let second = client.send().await.unwrap();
+    let timings = second.timings();
+    assert!(timings.reused);
+    assert_eq!(timings.setup(), Duration::ZERO);
+    assert_eq!(timings.remote_addr, Some(proxy.addr()));
+    assert_eq!(timings.total, timings.first_byte + timings.transfer);
+}
+
+#[derive(Default)]
+struct Events(Mutex<Vec<RequestEvent>>);
+
+impl MetricsObserver for Events {
+    fn on_request(&self, event: &RequestEvent) {
+        self.0.lock().unwrap().push(event.clone());
+    }
+}
+
+#[tokio::test]
+async fn test_metrics_observer() {
+    let mock_server = MockServer::start().await;
+    Mock::given(matchers::path("/old"))
+        .respond_with(ResponseTemplate::new(301).insert_header("Location", "/new"))
+        .mount(&mock_server)
+        .await;
+    Mock::given(matchers::path("/new"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("moved"))
+        .mount(&mock_server)
+        .await;
+    let proxy = HttpProxy::with_auth("user", "secret").await.unwrap();
+    let events = Arc::new(Events::default());
+    let response = Client::builder()
+        .get(&format!("{}/old", mock_server.uri()))
+        .proxy(&proxy.url())
+        .observer(events.clone())
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().unwrap(), "moved");
+    let recorded = events.0.lock().unwrap().clone();
+    assert_eq!(recorded.len(), 2);
+    assert_eq!(recorded[0].status, Some(301));
+    assert_eq!(recorded[0].status_class(), "3xx");
+    assert_eq!(recorded[1].status, Some(200));
+    assert_eq!(recorded[1].bytes_received, 5);
+    for event in &recorded {
+        assert_eq!(event.host, "127.0.0.1");
+        assert_eq!(event.method, Method::Get);
+        assert_eq!(event.error, None);
+        let proxy_url = event.proxy.as_ref().unwrap();
+        assert_eq!(proxy_url.port(), Some(proxy.addr().port()));
+        assert_eq!((proxy_url.username(), proxy_url.password()), ("", None));
+    }
+
+    let server = TestServer::start(vec![Reply::raw("HTTP/1.1 OK\r\n\r\n")])
+        .await
+        .unwrap();
+    let events = Arc::new(Events::default());
+    let result = Client::builder()
+        .post(&server.url("/"))
+        .body("data")
+        .observer(events.clone())
+        .build()
+        .await
+        .unwrap()
+        .send()
+        .await;
+    assert!(result.is_err());
+    let recorded = events.0.lock().unwrap().clone();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(recorded[0].method, Method::Post);
+    assert_eq!(
+        (recorded[0].status, recorded[0].status_class()),
+        (None, "error")
+    );
+    assert_eq!(recorded[0].error, Some("protocol"));
+    assert_eq!(recorded[0].bytes_sent, 4);
+    assert_eq!(recorded[0].proxy, None);
+}
+
+#[tokio::test]
+async fn test_metrics_observer_connect_errors() {
+    let events = Arc::new(Events::default());
+    let result = Client::builder()
+        .get("http://127.0.0.1:1/")
+        .observer(events.clone())
+        .build()
+        .await;
+    assert!(result.is_err());
+
+    let proxy = SocksProxy::with_auth("test", "tset").await.unwrap();
+    let mock_server = mock_get("streamed").await;
+    let result = Client::builder()
+        .get(&mock_server.uri())
+        .proxy(&format!("socks5://test:wrong@{}", proxy.addr()))
+        .observer(events.clone())
+        .build()
+        .await;
+    assert!(result.is_err());
+
+    let (response, body) = Client::builder()
+        .get(&mock_server.uri())
+        .observer(events.clone())
+        .build()
+        .await
+        .unwrap()
+        .send_streaming()
+        .await
+        .unwrap();
+    assert_eq!(&body.bytes().await.unwrap()[..], b"streamed");
     assert!(response.status_code().is_success());
-    assert_eq!(&response.text().unwrap(), test_var);
+
+    let recorded = events.0.lock().unwrap().clone();
+    assert_eq!(recorded.len(), 3);
+    assert_eq!((recorded[0].status, recorded[0].error), (None, Some("io")));
+    assert_eq!(
+        (recorded[1].status, recorded[1].error),
+        (None, Some("proxy"))
+    );
+    let proxy_url = recorded[1].proxy.as_ref().unwrap();
+    assert_eq!((proxy_url.username(), proxy_url.password()), ("", None));
+    assert_eq!((recorded[2].status, recorded[2].error), (Some(200), None));
+    assert_eq!(recorded[2].bytes_received, 0);
 }
 
 #[tokio::test]
-async fn test_socks_proxy_auth_err() {
-    let test_var = "TEST_SOCKS5_AUTH_ERR_PROXY";
-    dotenvy::dotenv().ok();
-    let proxy = match dotenvy::var(test_var) {
-        Ok(it) => it,
-        _ => return,
-    };
+async fn test_download_status() {
     let mock_server = MockServer::start().await;
     Mock::given(matchers::method("GET"))
-        .respond_with(ResponseTemplate::new(200).set_body_string(test_var))
+        .respond_with(ResponseTemplate::new(404))
         .mount(&mock_server)
         .await;
-    let client = Client::builder()
+    let path = std::env::temp_dir().join(format!("netc-missing-{}", std::process::id()));
+    let result = Client::builder()
         .get(&mock_server.uri())
-        .proxy(&proxy)
-        .build()
+        .download_to(&path)
         .await;
-    assert!(client.is_err());
+    assert_eq!(result, Err(Error::DownloadStatus(404)));
+    assert!(!path.exists());
+    std::fs::write(&path, "kept").unwrap();
+    let result = Client::builder()
+        .get(&mock_server.uri())
+        .download_to(&path)
+        .await;
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result, Err(Error::DownloadStatus(404)));
+    assert_eq!(content, "kept");
+    let mut partial = path.into_os_string();
+    partial.push(".partial");
+    assert!(!std::path::Path::new(&partial).exists());
 }
 
 // #[tokio::test]